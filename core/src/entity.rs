@@ -0,0 +1,221 @@
+use alloc::{vec, vec::Vec};
+
+use crate::parse::{Attribute, Element};
+
+// Attributes which are exposed as fields on Entity and are left out of the attribute bag.
+const COMMON_ATTRIBUTES: [&str; 7] = ["id", "x", "y", "width", "height", "originX", "originY"];
+
+#[derive(Debug, Clone)]
+pub struct Entity<'a> {
+    pub name: &'a str,
+    pub id: Option<i64>,
+    pub x: f64,
+    pub y: f64,
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    pub origin_x: Option<f64>,
+    pub origin_y: Option<f64>,
+    // Node positions are relative to the room, like the entity position.
+    pub nodes: Vec<(f64, f64)>,
+    pub attributes: Vec<(&'a str, &'a Attribute)>,
+}
+
+fn get_number(element: &Element, name: &str) -> Option<f64> {
+    element.get_attribute(name)?.as_number()
+}
+
+impl<'a> Entity<'a> {
+    pub fn from_element(element: &'a Element) -> Option<Entity<'a>> {
+        let x = get_number(element, "x")?;
+        let y = get_number(element, "y")?;
+
+        // A node without a position is left out, the entity itself is still usable.
+        let nodes = element
+            .children
+            .iter()
+            .filter(|child| child.name == "node")
+            .filter_map(|node| Some((get_number(node, "x")?, get_number(node, "y")?)))
+            .collect();
+
+        let attributes = element
+            .attributes
+            .iter()
+            .filter(|attribute| !COMMON_ATTRIBUTES.contains(&attribute.0.as_str()))
+            .map(|attribute| (attribute.0.as_str(), &attribute.1))
+            .collect();
+
+        Some(Entity {
+            name: &element.name,
+            id: element
+                .get_attribute("id")
+                .and_then(|attribute| attribute.as_integer()),
+            x,
+            y,
+            width: get_number(element, "width"),
+            height: get_number(element, "height"),
+            origin_x: get_number(element, "originX"),
+            origin_y: get_number(element, "originY"),
+            nodes,
+            attributes,
+        })
+    }
+
+    pub fn get_attribute(&self, name: &str) -> Option<&'a Attribute> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.0 == name)
+            .map(|attribute| attribute.1)
+    }
+
+    // Position in map coordinates, room is the level element containing the entity.
+    pub fn absolute_position(&self, room: &Element) -> Option<(f64, f64)> {
+        let room_x = get_number(room, "x")?;
+        let room_y = get_number(room, "y")?;
+        Some((room_x + self.x, room_y + self.y))
+    }
+
+    pub fn absolute_nodes(&self, room: &Element) -> Option<Vec<(f64, f64)>> {
+        let room_x = get_number(room, "x")?;
+        let room_y = get_number(room, "y")?;
+        Some(
+            self.nodes
+                .iter()
+                .map(|node| (room_x + node.0, room_y + node.1))
+                .collect(),
+        )
+    }
+}
+
+// Entities of a room, elements which aren't entities (missing position) are skipped.
+pub fn entities(room: &Element) -> Vec<Entity<'_>> {
+    let Some(entities) = room.get_child("entities") else {
+        return vec![];
+    };
+
+    entities
+        .children
+        .iter()
+        .filter_map(Entity::from_element)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+
+    use super::*;
+
+    fn element(name: &str, attributes: Vec<(&str, Attribute)>, children: Vec<Element>) -> Element {
+        Element {
+            name: name.to_string(),
+            attributes: attributes
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            children,
+        }
+    }
+
+    fn node(x: Attribute, y: Attribute) -> Element {
+        element("node", vec![("x", x), ("y", y)], vec![])
+    }
+
+    fn spring() -> Element {
+        element(
+            "spring",
+            vec![
+                ("id", Attribute::Int(7)),
+                ("x", Attribute::Int(16)),
+                ("y", Attribute::Float(24.5)),
+                ("width", Attribute::Short(8)),
+                ("originX", Attribute::Byte(4)),
+                ("playerCanUse", Attribute::Bool(true)),
+                ("sprite", Attribute::String(String::from("red"))),
+            ],
+            vec![
+                node(Attribute::Int(32), Attribute::Int(40)),
+                element("other", vec![], vec![]),
+                node(Attribute::Double(1.5), Attribute::Long(-2)),
+            ],
+        )
+    }
+
+    #[test]
+    fn reads_common_fields_and_nodes() {
+        let element = spring();
+        let entity = Entity::from_element(&element).unwrap();
+        assert_eq!(entity.name, "spring");
+        assert_eq!(entity.id, Some(7));
+        assert_eq!((entity.x, entity.y), (16.0, 24.5));
+        assert_eq!(entity.width, Some(8.0));
+        assert_eq!(entity.height, None);
+        assert_eq!(entity.origin_x, Some(4.0));
+        assert_eq!(entity.nodes, vec![(32.0, 40.0), (1.5, -2.0)]);
+    }
+
+    #[test]
+    fn attribute_bag_leaves_out_common_attributes() {
+        let element = spring();
+        let entity = Entity::from_element(&element).unwrap();
+        let names = entity
+            .attributes
+            .iter()
+            .map(|attribute| attribute.0)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["playerCanUse", "sprite"]);
+        assert_eq!(
+            entity
+                .get_attribute("playerCanUse")
+                .and_then(Attribute::as_bool),
+            Some(true)
+        );
+        assert!(entity.get_attribute("x").is_none());
+    }
+
+    #[test]
+    fn bad_node_is_skipped() {
+        let mut element = spring();
+        element.children.push(node(
+            Attribute::String(String::from("left")),
+            Attribute::Int(0),
+        ));
+        let entity = Entity::from_element(&element).unwrap();
+        assert_eq!(entity.nodes.len(), 2);
+    }
+
+    #[test]
+    fn missing_position_is_not_an_entity() {
+        let element = element("spring", vec![("x", Attribute::Int(0))], vec![]);
+        assert!(Entity::from_element(&element).is_none());
+    }
+
+    #[test]
+    fn absolute_position_adds_the_room_position() {
+        let room = element(
+            "level",
+            vec![("x", Attribute::Int(100)), ("y", Attribute::Int(-50))],
+            vec![element(
+                "entities",
+                vec![],
+                vec![
+                    spring(),
+                    element("decal", vec![("texture", Attribute::Int(1))], vec![]),
+                ],
+            )],
+        );
+        let entities = entities(&room);
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].absolute_position(&room), Some((116.0, -25.5)));
+        assert_eq!(
+            entities[0].absolute_nodes(&room),
+            Some(vec![(132.0, -10.0), (101.5, -52.0)])
+        );
+
+        let room_without_position = element("level", vec![], vec![]);
+        assert_eq!(entities[0].absolute_position(&room_without_position), None);
+    }
+}
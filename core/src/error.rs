@@ -0,0 +1,43 @@
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    MissingHeader,
+    // The map ended while the named value was being read.
+    UnexpectedEnd(&'static str),
+    BadStringLength,
+    StringOutOfBounds,
+    InvalidUtf8,
+    NegativeLookupTableSize,
+    NegativeLookupIndex,
+    LookupIndexOutOfBounds,
+    NegativeStringLength,
+    OddEncodedStringLength,
+    UnknownValueType(u8),
+    NegativeChildCount,
+    TrailingBytes,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingHeader => write!(f, "Map should start with CELESTE MAP"),
+            Self::UnexpectedEnd(expected) => write!(f, "Expected {}", expected),
+            Self::BadStringLength => write!(f, "Bad string length"),
+            Self::StringOutOfBounds => write!(f, "String length exceeds beyond map"),
+            Self::InvalidUtf8 => write!(f, "Bad string"),
+            Self::NegativeLookupTableSize => write!(f, "Lookup table size is negative"),
+            Self::NegativeLookupIndex => write!(f, "Lookup index is negative"),
+            Self::LookupIndexOutOfBounds => write!(f, "Lookup index exceeds the table length"),
+            Self::NegativeStringLength => write!(f, "Negative string length"),
+            Self::OddEncodedStringLength => write!(f, "Length encoded string has odd length"),
+            Self::UnknownValueType(value_type) => {
+                write!(f, "Unspecified encoded value {}", value_type)
+            }
+            Self::NegativeChildCount => write!(f, "Negative child count"),
+            Self::TrailingBytes => write!(f, "Extra bytes after parsing root"),
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
@@ -0,0 +1,9 @@
+#![no_std]
+
+extern crate alloc;
+
+pub mod entity;
+mod error;
+pub mod parse;
+
+pub use error::ParseError;
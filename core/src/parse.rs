@@ -1,5 +1,10 @@
 // https://github.com/iSkLz/celestial-compass/blob/master/article.md
 
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+
+use crate::ParseError;
+
 #[derive(Debug, Clone)]
 pub enum Attribute {
     Bool(bool),
@@ -31,6 +36,13 @@ impl Attribute {
         }
     }
 
+    // Integer or real value as a f64, positions can be stored as either.
+    pub fn as_number(&self) -> Option<f64> {
+        self.as_integer()
+            .map(|x| x as f64)
+            .or_else(|| self.as_real())
+    }
+
     pub fn as_bool(&self) -> Option<bool> {
         if let Self::Bool(x) = self {
             Some(*x)
@@ -48,6 +60,21 @@ impl Attribute {
     }
 }
 
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(x) => write!(f, "{}", x),
+            Self::Byte(x) => write!(f, "{}", x),
+            Self::Short(x) => write!(f, "{}", x),
+            Self::Int(x) => write!(f, "{}", x),
+            Self::Float(x) => write!(f, "{}", x),
+            Self::String(x) => write!(f, "{:?}", x),
+            Self::Long(x) => write!(f, "{}", x),
+            Self::Double(x) => write!(f, "{}", x),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Element {
     pub name: String,
@@ -75,17 +102,17 @@ pub struct Map {
 }
 
 // Parse string with 7-bit length encoding.
-fn parse_string(bytes: &[u8], curr: &mut usize) -> Result<String, &'static str> {
+fn parse_string(bytes: &[u8], curr: &mut usize) -> Result<String, ParseError> {
     let mut string_length: usize = 0; // Can cause a problem on 32 bit.
     let mut bits_added = 0;
 
     loop {
         if bits_added == 35 {
-            return Err("Bad string length");
+            return Err(ParseError::BadStringLength);
         }
 
         let Some(&byte) = bytes.get(*curr) else {
-            return Err("Bad string length");
+            return Err(ParseError::BadStringLength);
         };
         *curr += 1;
 
@@ -98,15 +125,16 @@ fn parse_string(bytes: &[u8], curr: &mut usize) -> Result<String, &'static str>
     }
 
     if *curr + string_length > bytes.len() {
-        return Err("String length exceeds beyond map");
+        return Err(ParseError::StringOutOfBounds);
     }
     *curr += string_length;
-    String::from_utf8(bytes[*curr - string_length..*curr].into()).map_err(|_| "Bad string")
+    String::from_utf8(bytes[*curr - string_length..*curr].into())
+        .map_err(|_| ParseError::InvalidUtf8)
 }
 
-fn parse_short(bytes: &[u8], curr: &mut usize) -> Result<i16, &'static str> {
+fn parse_short(bytes: &[u8], curr: &mut usize) -> Result<i16, ParseError> {
     if *curr + 2 > bytes.len() {
-        Err("Expected short")
+        Err(ParseError::UnexpectedEnd("short"))
     } else {
         let res = i16::from_le_bytes([bytes[*curr], bytes[*curr + 1]]);
         *curr += 2;
@@ -118,16 +146,16 @@ fn parse_lookup_string(
     bytes: &[u8],
     curr: &mut usize,
     lookup_table: &[String],
-) -> Result<String, &'static str> {
+) -> Result<String, ParseError> {
     let index = parse_short(bytes, curr)?;
     if index < 0 {
-        return Err("Lookup index is negative");
+        return Err(ParseError::NegativeLookupIndex);
     }
 
     if let Some(string) = lookup_table.get(index as usize) {
         Ok(string.clone())
     } else {
-        Err("Lookup index exceeds the table length")
+        Err(ParseError::LookupIndexOutOfBounds)
     }
 }
 
@@ -135,11 +163,11 @@ fn parse_element(
     bytes: &[u8],
     curr: &mut usize,
     lookup_table: &[String],
-) -> Result<Element, &'static str> {
+) -> Result<Element, ParseError> {
     let name = parse_lookup_string(bytes, curr, lookup_table)?;
 
     let Some(&attribute_count) = bytes.get(*curr) else {
-        return Err("Expected byte");
+        return Err(ParseError::UnexpectedEnd("byte"));
     }; // TODO: Should this be a i8 or u8?
     *curr += 1;
 
@@ -147,12 +175,14 @@ fn parse_element(
     for _ in 0..attribute_count {
         let attribute_name = parse_lookup_string(bytes, curr, lookup_table)?;
 
-        let encoded_value_type = bytes[*curr];
+        let Some(&encoded_value_type) = bytes.get(*curr) else {
+            return Err(ParseError::UnexpectedEnd("value type"));
+        };
         *curr += 1;
         let attribute = match encoded_value_type {
             0 => {
                 let Some(&byte) = bytes.get(*curr) else {
-                    return Err("Expected boolean");
+                    return Err(ParseError::UnexpectedEnd("boolean"));
                 };
                 *curr += 1;
                 let boolean = byte != 0;
@@ -160,7 +190,7 @@ fn parse_element(
             }
             1 => {
                 let Some(&byte) = bytes.get(*curr) else {
-                    return Err("Expected byte");
+                    return Err(ParseError::UnexpectedEnd("byte"));
                 };
                 *curr += 1;
                 Attribute::Byte(byte)
@@ -171,7 +201,7 @@ fn parse_element(
             }
             3 => {
                 if *curr + 4 > bytes.len() {
-                    return Err("Expected int");
+                    return Err(ParseError::UnexpectedEnd("int"));
                 }
                 let int = i32::from_le_bytes([
                     bytes[*curr],
@@ -184,7 +214,7 @@ fn parse_element(
             }
             4 => {
                 if *curr + 4 > bytes.len() {
-                    return Err("Expected float");
+                    return Err(ParseError::UnexpectedEnd("float"));
                 }
                 let float = f32::from_le_bytes([
                     bytes[*curr],
@@ -200,13 +230,13 @@ fn parse_element(
             7 => {
                 let string_length = parse_short(bytes, curr)?;
                 if string_length < 0 {
-                    return Err("Negative string length");
+                    return Err(ParseError::NegativeStringLength);
                 }
                 if string_length % 2 == 1 {
-                    return Err("Length encoded string has odd length");
+                    return Err(ParseError::OddEncodedStringLength);
                 }
                 if *curr + string_length as usize > bytes.len() {
-                    return Err("String length exceeds beyond map");
+                    return Err(ParseError::StringOutOfBounds);
                 }
 
                 let mut string = String::new();
@@ -222,7 +252,7 @@ fn parse_element(
             }
             8 => {
                 if *curr + 8 > bytes.len() {
-                    return Err("Expected long");
+                    return Err(ParseError::UnexpectedEnd("long"));
                 }
                 let long = i64::from_le_bytes([
                     bytes[*curr],
@@ -239,7 +269,7 @@ fn parse_element(
             }
             9 => {
                 if *curr + 8 > bytes.len() {
-                    return Err("Expected double");
+                    return Err(ParseError::UnexpectedEnd("double"));
                 }
                 let double = f64::from_le_bytes([
                     bytes[*curr],
@@ -255,7 +285,7 @@ fn parse_element(
                 Attribute::Double(double)
             }
             _ => {
-                return Err("Unspecified encoded value.");
+                return Err(ParseError::UnknownValueType(encoded_value_type));
             }
         };
 
@@ -264,7 +294,7 @@ fn parse_element(
 
     let child_count = parse_short(bytes, curr)?;
     if child_count < 0 {
-        return Err("Negative child count");
+        return Err(ParseError::NegativeChildCount);
     }
 
     let mut children = vec![];
@@ -279,9 +309,9 @@ fn parse_element(
     })
 }
 
-pub fn parse(map: &[u8]) -> Result<Map, &'static str> {
+pub fn parse(map: &[u8]) -> Result<Map, ParseError> {
     if map.len() < 12 || map[0] != 11 || &map[1..12] != "CELESTE MAP".as_bytes() {
-        return Err("Map should start with CELESTE MAP");
+        return Err(ParseError::MissingHeader);
     }
 
     let mut curr = 12;
@@ -289,10 +319,10 @@ pub fn parse(map: &[u8]) -> Result<Map, &'static str> {
     let package_name = parse_string(map, &mut curr)?;
 
     let Ok(lookup_table_size) = parse_short(map, &mut curr) else {
-        return Err("Bad lookup table size");
+        return Err(ParseError::UnexpectedEnd("lookup table size"));
     };
     if lookup_table_size < 0 {
-        return Err("Lookup table size is negative");
+        return Err(ParseError::NegativeLookupTableSize);
     }
 
     let mut lookup_table = vec![];
@@ -303,7 +333,7 @@ pub fn parse(map: &[u8]) -> Result<Map, &'static str> {
     let root = parse_element(map, &mut curr, &lookup_table)?;
 
     if curr != map.len() {
-        return Err("Extra bytes after parsing root");
+        return Err(ParseError::TrailingBytes);
     }
 
     Ok(Map { package_name, root })
@@ -0,0 +1,49 @@
+use std::{env, path::Path, process::Command};
+
+// Targets without std, the crate has to build for them as it's used from embedded and WASM hosts.
+const TARGETS: [&str; 2] = ["thumbv7em-none-eabihf", "wasm32-unknown-unknown"];
+
+fn is_installed(target: &str) -> bool {
+    let Ok(output) = Command::new("rustc").args(["--print", "sysroot"]).output() else {
+        return false;
+    };
+    let sysroot = String::from_utf8_lossy(&output.stdout);
+    Path::new(sysroot.trim())
+        .join("lib")
+        .join("rustlib")
+        .join(target)
+        .is_dir()
+}
+
+// Install a target with `rustup target add thumbv7em-none-eabihf` to check it, missing targets are
+// reported and skipped. At least one has to be installed.
+#[test]
+fn builds_without_std() {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let mut checked = 0;
+    for target in TARGETS {
+        if !is_installed(target) {
+            eprintln!("Skipping {}, the target isn't installed", target);
+            continue;
+        }
+        let status = Command::new(&cargo)
+            .args(["check", "--no-default-features", "--target", target])
+            .arg("--manifest-path")
+            .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+            .arg("--target-dir")
+            .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_std"))
+            .status()
+            .unwrap();
+        assert!(
+            status.success(),
+            "celeste-maps-core doesn't build for {}",
+            target
+        );
+        checked += 1;
+    }
+    assert!(
+        checked > 0,
+        "None of {:?} is installed, add one with rustup target add",
+        TARGETS
+    );
+}
@@ -0,0 +1,189 @@
+use celeste_maps_core::{
+    parse::{parse, Attribute},
+    ParseError,
+};
+
+fn string(value: &str) -> Vec<u8> {
+    assert!(value.len() < 0x80);
+    let mut bytes = vec![value.len() as u8];
+    bytes.extend_from_slice(value.as_bytes());
+    bytes
+}
+
+// Header, package name and a lookup table of the given strings, the root element comes next.
+fn map_start(lookup_table: &[&str]) -> Vec<u8> {
+    let mut bytes = vec![11];
+    bytes.extend_from_slice(b"CELESTE MAP");
+    bytes.extend(string("test"));
+    bytes.extend((lookup_table.len() as i16).to_le_bytes());
+    for value in lookup_table {
+        bytes.extend(string(value));
+    }
+    bytes
+}
+
+// A root element named by lookup index 0 with one attribute named by index 1, whose value type and
+// encoded value are given. The element has no children.
+fn map_with_attribute(value_type: u8, value: &[u8]) -> Vec<u8> {
+    let mut bytes = map_start(&["Map", "attribute"]);
+    bytes.extend(0i16.to_le_bytes());
+    bytes.push(1);
+    bytes.extend(1i16.to_le_bytes());
+    bytes.push(value_type);
+    bytes.extend_from_slice(value);
+    bytes.extend(0i16.to_le_bytes());
+    bytes
+}
+
+fn error(map: &[u8]) -> ParseError {
+    parse(map).unwrap_err()
+}
+
+#[test]
+fn parses_every_value_type() {
+    let values: [(u8, Vec<u8>); 9] = [
+        (0, vec![1]),
+        (1, vec![200]),
+        (2, (-3i16).to_le_bytes().to_vec()),
+        (3, 70000i32.to_le_bytes().to_vec()),
+        (4, 1.5f32.to_le_bytes().to_vec()),
+        (5, 0i16.to_le_bytes().to_vec()),
+        (6, string("plain")),
+        // Run-length encoded: three a, one b.
+        (
+            7,
+            [4i16.to_le_bytes().to_vec(), vec![3, b'a', 1, b'b']].concat(),
+        ),
+        (8, (-5i64).to_le_bytes().to_vec()),
+    ];
+    let expected = [
+        "true",
+        "200",
+        "-3",
+        "70000",
+        "1.5",
+        "\"Map\"",
+        "\"plain\"",
+        "\"aaab\"",
+        "-5",
+    ];
+    for ((value_type, value), expected) in values.iter().zip(expected) {
+        let map = parse(&map_with_attribute(*value_type, value)).unwrap();
+        assert_eq!(map.package_name, "test");
+        assert_eq!(map.root.name, "Map");
+        let attribute = map.root.get_attribute("attribute").unwrap();
+        assert_eq!(attribute.to_string(), expected, "value type {}", value_type);
+    }
+
+    let map = parse(&map_with_attribute(9, &2.25f64.to_le_bytes())).unwrap();
+    assert!(matches!(
+        map.root.get_attribute("attribute"),
+        Some(Attribute::Double(value)) if *value == 2.25
+    ));
+}
+
+#[test]
+fn missing_header() {
+    assert_eq!(error(b""), ParseError::MissingHeader);
+    assert_eq!(error(b"\x0bCELESTE MAX"), ParseError::MissingHeader);
+}
+
+#[test]
+fn bad_strings() {
+    let mut map = map_start(&[]);
+    map.truncate(12);
+    // The length never ends.
+    map.extend([0x80; 5]);
+    assert_eq!(error(&map), ParseError::BadStringLength);
+
+    map.truncate(12);
+    map.push(0x80);
+    assert_eq!(error(&map), ParseError::BadStringLength);
+
+    map.truncate(12);
+    map.extend([10, b'a']);
+    assert_eq!(error(&map), ParseError::StringOutOfBounds);
+
+    map.truncate(12);
+    map.extend([1, 0xff]);
+    assert_eq!(error(&map), ParseError::InvalidUtf8);
+}
+
+#[test]
+fn bad_lookup_table() {
+    let mut map = map_start(&[]);
+    map.truncate(map.len() - 2);
+    assert_eq!(error(&map), ParseError::UnexpectedEnd("lookup table size"));
+
+    map.extend((-1i16).to_le_bytes());
+    assert_eq!(error(&map), ParseError::NegativeLookupTableSize);
+
+    let mut map = map_start(&["Map"]);
+    map.extend((-1i16).to_le_bytes());
+    assert_eq!(error(&map), ParseError::NegativeLookupIndex);
+
+    let mut map = map_start(&["Map"]);
+    map.extend(1i16.to_le_bytes());
+    assert_eq!(error(&map), ParseError::LookupIndexOutOfBounds);
+}
+
+#[test]
+fn truncated_element() {
+    let mut map = map_start(&["Map", "attribute"]);
+    map.extend(0i16.to_le_bytes());
+    assert_eq!(error(&map), ParseError::UnexpectedEnd("byte"));
+
+    map.push(1);
+    map.extend(1i16.to_le_bytes());
+    assert_eq!(error(&map), ParseError::UnexpectedEnd("value type"));
+
+    for (value_type, expected) in [
+        (0, "boolean"),
+        (1, "byte"),
+        (3, "int"),
+        (4, "float"),
+        (8, "long"),
+        (9, "double"),
+    ] {
+        let mut map = map_with_attribute(value_type, &[]);
+        // Drop the child count so the value is cut off.
+        map.truncate(map.len() - 2);
+        assert_eq!(error(&map), ParseError::UnexpectedEnd(expected));
+    }
+
+    let mut map = map_with_attribute(0, &[1]);
+    map.truncate(map.len() - 1);
+    assert_eq!(error(&map), ParseError::UnexpectedEnd("short"));
+}
+
+#[test]
+fn bad_values() {
+    assert_eq!(
+        error(&map_with_attribute(42, &[])),
+        ParseError::UnknownValueType(42)
+    );
+    assert_eq!(
+        error(&map_with_attribute(7, &(-2i16).to_le_bytes())),
+        ParseError::NegativeStringLength
+    );
+    assert_eq!(
+        error(&map_with_attribute(7, &3i16.to_le_bytes())),
+        ParseError::OddEncodedStringLength
+    );
+    assert_eq!(
+        error(&map_with_attribute(7, &40i16.to_le_bytes())),
+        ParseError::StringOutOfBounds
+    );
+}
+
+#[test]
+fn bad_children() {
+    let mut map = map_with_attribute(0, &[1]);
+    map.truncate(map.len() - 2);
+    map.extend((-1i16).to_le_bytes());
+    assert_eq!(error(&map), ParseError::NegativeChildCount);
+
+    let mut map = map_with_attribute(0, &[1]);
+    map.push(0);
+    assert_eq!(error(&map), ParseError::TrailingBytes);
+}
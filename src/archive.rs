@@ -0,0 +1,330 @@
+use std::{
+    error::Error,
+    ffi::OsStr,
+    fmt,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use zip::{result::ZipError, CompressionMethod, ZipArchive};
+
+use crate::config::ZipLimits;
+
+// Entries under this size aren't checked for their compression ratio.
+const RATIO_CHECK_MIN_SIZE: u64 = 1 << 20;
+
+#[derive(Debug, Clone)]
+pub struct MapFile {
+    // Path of the map inside the zip, or the file path for loose maps.
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+impl MapFile {
+    // Name without the directories and the .bin extension.
+    pub fn short_name(&self) -> &str {
+        let file_name = self.name.rsplit('/').next().unwrap_or(&self.name);
+        file_name.strip_suffix(".bin").unwrap_or(file_name)
+    }
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension() == Some(OsStr::new(extension))
+}
+
+// Why a zip was rejected or an entry couldn't be read.
+#[derive(Debug)]
+pub enum ArchiveError {
+    TooLarge {
+        size: u64,
+        limit: u64,
+    },
+    UncompressedTooLarge {
+        size: u64,
+        limit: u64,
+    },
+    TooManyEntries {
+        count: usize,
+        limit: usize,
+    },
+    CompressionRatio {
+        entry: String,
+        ratio: u64,
+        limit: u64,
+    },
+    UnsupportedCompression {
+        entry: String,
+        method: String,
+    },
+    Encrypted {
+        entry: String,
+    },
+    // Absolute paths, .. components or names which aren't UTF-8.
+    UnsafePath {
+        entry: String,
+    },
+    // The entry decompressed to more than its declared size.
+    SizeMismatch {
+        entry: String,
+    },
+    CrcMismatch {
+        entry: String,
+    },
+    Zip(ZipError),
+    Io(io::Error),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge { size, limit } => {
+                write!(f, "Zip is {} bytes, the limit is {}", size, limit)
+            }
+            Self::UncompressedTooLarge { size, limit } => write!(
+                f,
+                "Zip uncompresses to {} bytes, the limit is {}",
+                size, limit
+            ),
+            Self::TooManyEntries { count, limit } => {
+                write!(f, "Zip has {} entries, the limit is {}", count, limit)
+            }
+            Self::CompressionRatio {
+                entry,
+                ratio,
+                limit,
+            } => write!(
+                f,
+                "{} has a compression ratio of {}, the limit is {}",
+                entry, ratio, limit
+            ),
+            Self::UnsupportedCompression { entry, method } => {
+                write!(f, "{} uses unsupported compression {}", entry, method)
+            }
+            Self::Encrypted { entry } => write!(f, "{} is encrypted", entry),
+            Self::UnsafePath { entry } => write!(f, "Unsafe entry path {}", entry),
+            Self::SizeMismatch { entry } => write!(f, "{} is larger than declared", entry),
+            Self::CrcMismatch { entry } => write!(f, "{} fails its CRC check", entry),
+            Self::Zip(err) => write!(f, "Bad zip - {}", err),
+            Self::Io(err) => write!(f, "IO error - {}", err),
+        }
+    }
+}
+
+impl Error for ArchiveError {}
+
+impl From<ZipError> for ArchiveError {
+    fn from(err: ZipError) -> Self {
+        Self::Zip(err)
+    }
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    // With / separators.
+    pub name: String,
+    pub is_file: bool,
+    // Uncompressed, as declared in the zip.
+    pub size: u64,
+    pub crc32: u32,
+}
+
+// A zip whose size, entry count, compression ratios, compression methods and entry paths were
+// checked against the limits when it was opened. Reading an entry also checks its size and CRC.
+pub struct SafeZip {
+    archive: ZipArchive<File>,
+    entries: Vec<ZipEntry>,
+}
+
+impl SafeZip {
+    pub fn open(path: &Path, limits: &ZipLimits) -> Result<SafeZip, ArchiveError> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        if size > limits.max_compressed_size {
+            return Err(ArchiveError::TooLarge {
+                size,
+                limit: limits.max_compressed_size,
+            });
+        }
+
+        let mut archive = ZipArchive::new(file)?;
+        if archive.len() > limits.max_entries {
+            return Err(ArchiveError::TooManyEntries {
+                count: archive.len(),
+                limit: limits.max_entries,
+            });
+        }
+
+        let mut entries = vec![];
+        let mut uncompressed_size = 0u64;
+        for i in 0..archive.len() {
+            // Raw access only reads the headers, nothing is decompressed or decrypted.
+            let file = archive.by_index_raw(i)?;
+            let Some(name) = file.enclosed_name().and_then(Path::to_str) else {
+                return Err(ArchiveError::UnsafePath {
+                    entry: file.name().to_string(),
+                });
+            };
+            let name = name.replace('\\', "/");
+
+            match file.compression() {
+                CompressionMethod::Stored
+                | CompressionMethod::Deflated
+                | CompressionMethod::Bzip2
+                | CompressionMethod::Zstd => {}
+                CompressionMethod::Aes => return Err(ArchiveError::Encrypted { entry: name }),
+                method => {
+                    return Err(ArchiveError::UnsupportedCompression {
+                        entry: name,
+                        method: method.to_string(),
+                    })
+                }
+            }
+            if file.size() > RATIO_CHECK_MIN_SIZE {
+                let ratio = file.size() / file.compressed_size().max(1);
+                if ratio > limits.max_compression_ratio {
+                    return Err(ArchiveError::CompressionRatio {
+                        entry: name,
+                        ratio,
+                        limit: limits.max_compression_ratio,
+                    });
+                }
+            }
+            uncompressed_size = uncompressed_size.saturating_add(file.size());
+
+            let entry = ZipEntry {
+                name,
+                is_file: file.is_file(),
+                size: file.size(),
+                crc32: file.crc32(),
+            };
+            drop(file);
+            // The raw headers don't tell ZipCrypto apart, opening the entry does without reading it.
+            if let Err(ZipError::UnsupportedArchive(message)) = archive.by_index(i) {
+                if message == ZipError::PASSWORD_REQUIRED {
+                    return Err(ArchiveError::Encrypted { entry: entry.name });
+                }
+            }
+            entries.push(entry);
+        }
+        if uncompressed_size > limits.max_uncompressed_size {
+            return Err(ArchiveError::UncompressedTooLarge {
+                size: uncompressed_size,
+                limit: limits.max_uncompressed_size,
+            });
+        }
+
+        Ok(SafeZip { archive, entries })
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    // Reads at most the declared size, so a lying header can't make an entry decompress forever.
+    pub fn read(&mut self, index: usize) -> Result<Vec<u8>, ArchiveError> {
+        let entry = self.entries[index].name.clone();
+        let declared_size = self.entries[index].size;
+        let crc32 = self.entries[index].crc32;
+        let file = match self.archive.by_index(index) {
+            Err(ZipError::UnsupportedArchive(message))
+                if message == ZipError::PASSWORD_REQUIRED =>
+            {
+                return Err(ArchiveError::Encrypted { entry });
+            }
+            Err(ZipError::UnsupportedArchive(_)) => {
+                return Err(ArchiveError::UnsupportedCompression {
+                    entry,
+                    method: "unknown".to_string(),
+                });
+            }
+            file => file?,
+        };
+
+        let mut bytes = vec![];
+        // The zip reader checks the CRC when it reaches the end of the entry, by then every byte is
+        // in the buffer, so a CRC error can be told apart from others by checking it again.
+        match file.take(declared_size + 1).read_to_end(&mut bytes) {
+            Ok(_) if bytes.len() as u64 > declared_size => {
+                Err(ArchiveError::SizeMismatch { entry })
+            }
+            Ok(_) => Ok(bytes),
+            Err(_) if bytes.len() as u64 == declared_size && crc32fast::hash(&bytes) != crc32 => {
+                Err(ArchiveError::CrcMismatch { entry })
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Index of the first entry matching the predicate.
+    pub fn find(&self, predicate: impl Fn(&str) -> bool) -> Option<usize> {
+        self.entries.iter().position(|entry| predicate(&entry.name))
+    }
+}
+
+pub fn read_zip_maps(path: &Path, limits: &ZipLimits) -> Result<Vec<MapFile>, ArchiveError> {
+    let mut zip = SafeZip::open(path, limits)?;
+
+    let mut maps = vec![];
+    for i in 0..zip.entries().len() {
+        let entry = &zip.entries()[i];
+        if entry.is_file && has_extension(Path::new(&entry.name), "bin") {
+            let name = entry.name.clone();
+            maps.push(MapFile {
+                name,
+                bytes: zip.read(i)?,
+            });
+        }
+    }
+
+    Ok(maps)
+}
+
+// Reads a single .bin, every .bin inside a .zip, or both recursively from a directory.
+// Zips which fail the safety checks are skipped when reading a directory and added to rejected.
+pub fn read_map_files(
+    path: &Path,
+    limits: &ZipLimits,
+    rejected: &mut Vec<(PathBuf, Box<dyn Error>)>,
+) -> Result<Vec<MapFile>, Box<dyn Error>> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+
+        let mut maps = vec![];
+        for entry in entries {
+            if entry.is_dir() || has_extension(&entry, "bin") {
+                maps.extend(read_map_files(&entry, limits, rejected)?);
+            } else if has_extension(&entry, "zip") {
+                let zip_maps = match read_zip_maps(&entry, limits) {
+                    Ok(zip_maps) => zip_maps,
+                    Err(err) => {
+                        rejected.push((entry, err.into()));
+                        continue;
+                    }
+                };
+                // Keep the zip path in the name so maps from different mods can be told apart.
+                for mut map in zip_maps {
+                    map.name = format!("{}/{}", entry.display(), map.name);
+                    maps.push(map);
+                }
+            }
+        }
+        Ok(maps)
+    } else if has_extension(path, "zip") {
+        Ok(read_zip_maps(path, limits)?)
+    } else {
+        Ok(vec![MapFile {
+            name: path.to_str().ok_or("Not a string")?.to_string(),
+            bytes: fs::read(path)?,
+        }])
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+// Loaded when no --config is given and the file exists in the working directory.
+pub const DEFAULT_CONFIG_PATH: &str = "celeste-maps-data.toml";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub paths: PathsConfig,
+    pub download: DownloadConfig,
+    pub upload: UploadConfig,
+    pub zip: ZipLimits,
+    pub budget: BudgetConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    // Downloaded mod zips, named <GameBanana ID>.zip.
+    pub mods_dir: PathBuf,
+    // Cached copy of the mod database, defaults to <mods_dir>/mods_list.yaml.
+    pub mods_list: Option<PathBuf>,
+    // Generated files like reports and statistics.
+    pub output_dir: PathBuf,
+    // What each download run did. Defaults to <output_dir>/run_reports/<start time in ms>.json, so
+    // every run keeps its own report, a path set here is overwritten by each run.
+    pub run_report: Option<PathBuf>,
+    // Maps and metadata extracted from the zips, see download::store.
+    pub store_dir: PathBuf,
+    // A Celeste install whose vanilla and installed maps are uploaded too, see install.
+    pub celeste_dir: Option<PathBuf>,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        PathsConfig {
+            mods_dir: PathBuf::from("mods"),
+            mods_list: None,
+            output_dir: PathBuf::from("output"),
+            run_report: None,
+            store_dir: PathBuf::from("store"),
+            celeste_dir: None,
+        }
+    }
+}
+
+impl PathsConfig {
+    pub fn mods_list(&self) -> PathBuf {
+        self.mods_list
+            .clone()
+            .unwrap_or_else(|| self.mods_dir.join("mods_list.yaml"))
+    }
+
+    // Cached copy of everest_update.yaml.
+    pub fn everest_update(&self) -> PathBuf {
+        self.mods_dir.join("everest_update.yaml")
+    }
+
+    // Source file of every downloaded zip.
+    pub fn manifest(&self) -> PathBuf {
+        self.mods_dir.join("manifest.yaml")
+    }
+
+    pub fn mod_zip(&self, gamebanana_id: u64) -> PathBuf {
+        self.mods_dir.join(format!("{}.zip", gamebanana_id))
+    }
+
+    // Every file of a mod when downloading all files, see download::history.
+    pub fn mod_history_dir(&self, gamebanana_id: u64) -> PathBuf {
+        self.mods_dir.join(gamebanana_id.to_string())
+    }
+
+    pub fn mod_file_zip(&self, gamebanana_id: u64, file_id: &str) -> PathBuf {
+        self.mod_history_dir(gamebanana_id)
+            .join(format!("{}.zip", file_id))
+    }
+
+    pub fn mod_file_history(&self, gamebanana_id: u64) -> PathBuf {
+        self.mod_history_dir(gamebanana_id).join("files.yaml")
+    }
+
+    // Status of every mod the downloader has tried, see download::state.
+    pub fn download_state(&self) -> PathBuf {
+        self.mods_dir.join("state.json")
+    }
+
+    pub fn store_index(&self) -> PathBuf {
+        self.store_dir.join("index.json")
+    }
+
+    // Objects are named by their xxHash and spread over directories by its first two digits.
+    pub fn store_object(&self, hash: &str) -> PathBuf {
+        self.store_dir
+            .join("objects")
+            .join(&hash[..2.min(hash.len())])
+            .join(hash)
+    }
+
+    // started_ms is the unix time the run started in milliseconds. A report of a run which started
+    // in the same millisecond gets a suffix rather than being overwritten.
+    pub fn run_report(&self, started_ms: u64) -> PathBuf {
+        if let Some(run_report) = &self.run_report {
+            return run_report.clone();
+        }
+        let run_reports_dir = self.output_dir.join("run_reports");
+        let mut path = run_reports_dir.join(format!("{}.json", started_ms));
+        let mut suffix = 1;
+        while path.exists() {
+            path = run_reports_dir.join(format!("{}-{}.json", started_ms, suffix));
+            suffix += 1;
+        }
+        path
+    }
+
+    pub fn download_failures(&self) -> PathBuf {
+        self.output_dir.join("download_failures.json")
+    }
+
+    // Mod zips which failed the checks in archive::SafeZip during the last upload.
+    pub fn zip_violations(&self) -> PathBuf {
+        self.output_dir.join("zip_violations.json")
+    }
+
+    // Appended to when downloading with download::pipeline.
+    pub fn map_statistics(&self) -> PathBuf {
+        self.output_dir.join("map_statistics.jsonl")
+    }
+
+    pub fn dependency_graph(&self) -> PathBuf {
+        self.output_dir.join("dependencies.json")
+    }
+
+    pub fn mirror_health(&self) -> PathBuf {
+        self.output_dir.join("mirror_health.json")
+    }
+
+    // Downloads which failed checksum verification.
+    pub fn quarantine_dir(&self) -> PathBuf {
+        self.mods_dir.join("quarantine")
+    }
+
+    pub fn quarantined_zip(&self, gamebanana_id: u64) -> PathBuf {
+        self.quarantine_dir().join(format!("{}.zip", gamebanana_id))
+    }
+
+    pub fn quarantined_file_zip(&self, gamebanana_id: u64, file_id: &str) -> PathBuf {
+        self.quarantine_dir()
+            .join(format!("{}-{}.zip", gamebanana_id, file_id))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadConfig {
+    pub database_url: String,
+    // Which file lists the mods to select from, see ModIndex.
+    pub index: ModIndex,
+    // Maps Everest module names to GameBanana IDs when following dependencies.
+    pub everest_update_url: String,
+    // Read the database and files from a directory instead of the URLs, see download::source::LocalSource.
+    pub source_dir: Option<PathBuf>,
+    // Downloads running at the same time.
+    pub concurrency: usize,
+    pub per_host_concurrency: Option<usize>,
+    // Total bytes per second over all downloads.
+    pub bandwidth_limit: Option<u64>,
+    pub timeout_secs: u64,
+    // Attempts per mod, including the first one.
+    pub retries: u32,
+    // Exponential backoff starts at the base delay and doubles up to the max delay.
+    pub retry_base_delay_ms: u64,
+    pub retry_max_delay_secs: u64,
+    // Mods selected by category, "*" selects every category. Mods only in everest_update.yaml are
+    // Uncategorized and selected unless it's excluded. See download::select::Selection.
+    pub categories: Vec<String>,
+    pub exclude_categories: Vec<String>,
+    // GameBanana IDs which are always selected.
+    pub ids: Vec<u64>,
+    pub name_regex: Option<String>,
+    // Unix timestamps compared with the creation date of the latest file, before is exclusive.
+    pub created_after: Option<u64>,
+    pub created_before: Option<u64>,
+    // Also keep every file of each selected mod, not just the latest one.
+    pub all_files: bool,
+    // Tried in order when a download fails, see MirrorRule.
+    pub mirrors: Vec<MirrorRule>,
+    // Try the database URL before the mirrors instead of after them.
+    pub original_first: bool,
+    // Threads parsing downloaded maps when processing while downloading, defaults to the CPU count.
+    pub process_workers: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModIndex {
+    // Every mod on GameBanana in the Celeste section.
+    #[default]
+    ModSearchDatabase,
+    // Only mods with an everest.yaml, whatever their category. Their details are merged from the
+    // mod database, see download::everest_update::ModuleIndex::merge.
+    EverestUpdate,
+}
+
+// Rewrites URLs starting with prefix to replacement + rest of the URL + suffix, e.g.
+// prefix = "https://gamebanana.com/mmdl/", replacement = "https://mirror.example/", suffix = ".zip".
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MirrorRule {
+    pub name: String,
+    pub prefix: String,
+    pub replacement: String,
+    #[serde(default)]
+    pub suffix: String,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        DownloadConfig {
+            database_url: "https://maddie480.ovh/celeste/mod_search_database.yaml".to_string(),
+            index: ModIndex::default(),
+            everest_update_url: "https://maddie480.ovh/celeste/everest_update.yaml".to_string(),
+            source_dir: None,
+            concurrency: 100,
+            per_host_concurrency: None,
+            bandwidth_limit: None,
+            timeout_secs: 3 * 60,
+            retries: 3,
+            retry_base_delay_ms: 1000,
+            retry_max_delay_secs: 60,
+            categories: vec!["Maps".to_string()],
+            exclude_categories: vec![],
+            ids: vec![],
+            name_regex: None,
+            created_after: None,
+            created_before: None,
+            all_files: false,
+            mirrors: vec![],
+            original_first: true,
+            process_workers: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadConfig {
+    // Falls back to LIBSQL_URL and LIBSQL_AUTH_TOKEN (including from .env).
+    pub database_url: Option<String>,
+    pub auth_token: Option<String>,
+}
+
+// Disk limits of mods_dir and store_dir together, checked before each download with the sizes
+// from the database, see download::budget.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BudgetConfig {
+    pub max_total_size_mb: Option<u64>,
+    // Files over this size are skipped.
+    pub max_file_size_mb: Option<u64>,
+    // Files kept per mod when keeping every file, older ones are deleted after each download.
+    pub keep_versions: Option<usize>,
+}
+
+// Checked before anything is read from a mod zip, see archive::SafeZip.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ZipLimits {
+    // Size of the zip file in bytes.
+    pub max_compressed_size: u64,
+    // Total size of the entries in bytes.
+    pub max_uncompressed_size: u64,
+    // Uncompressed size over compressed size, only checked for entries over a megabyte.
+    pub max_compression_ratio: u64,
+    pub max_entries: usize,
+}
+
+impl Default for ZipLimits {
+    // The largest collabs are a few GB, far below these.
+    fn default() -> Self {
+        ZipLimits {
+            max_compressed_size: 8 << 30,
+            max_uncompressed_size: 16 << 30,
+            max_compression_ratio: 200,
+            max_entries: 200_000,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Config, Box<dyn Error>> {
+        let config = fs::read_to_string(path)
+            .map_err(|err| format!("Couldn't read config {}: {}", path.display(), err))?;
+        toml::from_str(&config)
+            .map_err(|err| format!("Bad config {}: {}", path.display(), err).into())
+    }
+
+    // An explicit path has to exist, otherwise the default path is used if present.
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn Error>> {
+        match path {
+            Some(path) => Config::from_file(path),
+            None if Path::new(DEFAULT_CONFIG_PATH).is_file() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_PATH))
+            }
+            None => Ok(Config::default()),
+        }
+    }
+}
@@ -1,19 +1,83 @@
-use serde::Deserialize;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
 use std::ffi::OsStr;
-use std::{error::Error, time::Duration};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use tokio::runtime::Runtime;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::task::JoinSet;
 use tokio::{fs, io::AsyncWriteExt};
 
-#[derive(Debug, Deserialize)]
+use crate::config::{Config, ModIndex, PathsConfig, ZipLimits};
+
+pub mod budget;
+pub mod checksum;
+pub mod dependencies;
+pub mod error;
+pub mod everest_update;
+pub mod history;
+pub mod manifest;
+pub mod mirror;
+pub mod pipeline;
+pub mod progress;
+pub mod retry;
+pub mod run_report;
+pub mod schedule;
+pub mod select;
+pub mod source;
+pub mod state;
+pub mod store;
+
+use budget::{Budget, Reservation};
+use dependencies::DependencyGraph;
+use error::{DownloadError, ErrorKind};
+use everest_update::{ModuleIndex, ModuleVersion};
+use history::FileHistory;
+use manifest::{Manifest, ManifestEntry};
+use mirror::Mirrors;
+use pipeline::Pipeline;
+use progress::{JsonLinesProgress, ProgressEvent, ProgressReporter, TerminalProgress};
+use retry::RetryPolicy;
+use run_report::{Outcome, RunReport};
+use schedule::Scheduler;
+use select::Selection;
+use source::{HttpSource, LocalSource, ModSource, SourceFuture};
+use state::DownloadState;
+use store::{ModFiles, StoreIndex};
+
+const MANIFEST_SAVE_INTERVAL: usize = 50;
+// Received bytes are reported in batches so a download doesn't send an event per chunk.
+const PROGRESS_BYTES_INTERVAL: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FileDetails {
     #[serde(rename = "URL")]
     pub url: String,
     #[serde(rename = "CreatedDate")]
     pub created_date: u64,
+    // xxHash64 checksums as hex strings.
+    #[serde(rename = "xxHash", default)]
+    pub xx_hash: Vec<String>,
+    // In bytes.
+    #[serde(rename = "Size", default)]
+    pub size: Option<u64>,
+    #[serde(rename = "Name", default)]
+    pub name: Option<String>,
+    #[serde(rename = "Description", default)]
+    pub description: Option<String>,
+    #[serde(rename = "Downloads", default)]
+    pub downloads: Option<u64>,
+    #[serde(rename = "HasEverestYaml", default)]
+    pub has_everest_yaml: Option<bool>,
+    // Fields which aren't known yet, kept so nothing in the database is lost.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_yaml::Value>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ModDetail {
     #[serde(rename = "Name")]
     pub name: String,
@@ -23,26 +87,301 @@ pub struct ModDetail {
     pub files: Vec<FileDetails>,
     #[serde(rename = "CategoryName")]
     pub category_name: String,
+    #[serde(rename = "CategoryId", default)]
+    pub category_id: Option<u64>,
+    #[serde(rename = "GameBananaType", default)]
+    pub gamebanana_type: Option<String>,
+    #[serde(rename = "Author", default)]
+    pub author: Option<String>,
+    #[serde(rename = "Submitter", default)]
+    pub submitter: Option<String>,
+    // Short description, the full description is in text (HTML).
+    #[serde(rename = "Description", default)]
+    pub description: Option<String>,
+    #[serde(rename = "Text", default)]
+    pub text: Option<String>,
+    #[serde(rename = "Views", default)]
+    pub views: Option<u64>,
+    #[serde(rename = "Likes", default)]
+    pub likes: Option<u64>,
+    #[serde(rename = "Downloads", default)]
+    pub downloads: Option<u64>,
+    // Unix timestamps.
+    #[serde(rename = "CreatedDate", default)]
+    pub created_date: Option<u64>,
+    #[serde(rename = "ModifiedDate", default)]
+    pub modified_date: Option<u64>,
+    // Screenshot URLs on GameBanana and on the mirror.
+    #[serde(rename = "Screenshots", default)]
+    pub screenshots: Vec<String>,
+    #[serde(rename = "MirroredScreenshots", default)]
+    pub mirrored_screenshots: Vec<String>,
+    #[serde(rename = "PageURL", default)]
+    pub page_url: Option<String>,
+    // Not in the mod database, filled in from everest_update.yaml, see ModuleIndex::add_modules.
+    #[serde(
+        rename = "EverestModules",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub everest_modules: Vec<ModuleVersion>,
+    // Fields which aren't known yet, kept so nothing in the database is lost.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_yaml::Value>,
 }
 
-async fn download_mod(
-    client: &reqwest::Client,
-    mod_detail: &ModDetail,
-) -> Result<(), Box<dyn Error>> {
-    let Some(latest_file) = mod_detail.files.iter().max_by_key(|file| file.created_date) else {
-        return Err("No files available".into());
+impl ModDetail {
+    pub fn latest_file(&self) -> Option<&FileDetails> {
+        self.files.iter().max_by_key(|file| file.created_date)
+    }
+}
+
+// Writes to a temporary file next to path and renames it into place, so an interrupted run can't
+// leave a truncated file behind.
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".tmp");
+    std::fs::write(&temporary_path, bytes)?;
+    std::fs::rename(temporary_path, path)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+// What a part file is a download of, kept next to it in <part>.source. A part file is only resumed
+// from the same URL with the same expected size, anything else is a partial of another file.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PartSource {
+    url: String,
+    size: Option<u64>,
+}
+
+fn part_source_path(part_path: &Path) -> PathBuf {
+    part_path.with_extension("part.source")
+}
+
+async fn read_part_source(part_path: &Path) -> Option<PartSource> {
+    let text = fs::read_to_string(part_source_path(part_path)).await.ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+// Removes the part file and what it's a download of, if they exist.
+async fn remove_part(part_path: &Path) -> io::Result<()> {
+    for path in [part_path.to_owned(), part_source_path(part_path)] {
+        match fs::remove_file(path).await {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Streams the file into part_path, continuing from its current length when the source supports it.
+async fn download_to_part(
+    downloader: &Downloader,
+    gamebanana_id: u64,
+    url: &str,
+    size: Option<u64>,
+    part_path: &Path,
+) -> Result<(), DownloadError> {
+    let part_source = PartSource {
+        url: url.to_string(),
+        size,
+    };
+    let resume_from = match fs::metadata(part_path).await {
+        Ok(metadata) if read_part_source(part_path).await.as_ref() == Some(&part_source) => {
+            metadata.len()
+        }
+        Ok(_) => {
+            remove_part(part_path).await?;
+            0
+        }
+        Err(_) => 0,
     };
 
-    let content = client.get(&latest_file.url).send().await?.bytes().await?;
-    let mut zip_file = fs::File::create(format!("mods/{}.zip", &mod_detail.gamebanana_id)).await?;
-    zip_file.write_all(&content).await?;
+    let mut fetched = match downloader.source.fetch_file(url, resume_from).await {
+        Err(DownloadError::RangeMismatch) => {
+            // The part file is as long as (or longer than) the file, or the server sent another
+            // range, start over on the next attempt.
+            remove_part(part_path).await?;
+            return Err(DownloadError::RangeMismatch);
+        }
+        fetched => fetched?,
+    };
+
+    let mut part_file = if fetched.resumed && resume_from > 0 {
+        fs::OpenOptions::new().append(true).open(part_path).await?
+    } else {
+        // Source ignored the range (or there was nothing to resume), so write from the start.
+        let part_source = serde_json::to_vec(&part_source).map_err(io::Error::other)?;
+        fs::write(part_source_path(part_path), part_source).await?;
+        fs::File::create(part_path).await?
+    };
+
+    let mut unreported_bytes = 0;
+    let result = async {
+        while let Some(chunk) = fetched.body.chunk().await? {
+            downloader.scheduler.consume_bandwidth(chunk.len()).await;
+            part_file.write_all(&chunk).await?;
+            unreported_bytes += chunk.len() as u64;
+            if unreported_bytes >= PROGRESS_BYTES_INTERVAL {
+                downloader.progress.report(&ProgressEvent::BytesReceived {
+                    gamebanana_id,
+                    bytes: unreported_bytes,
+                });
+                unreported_bytes = 0;
+            }
+        }
+        Ok::<_, DownloadError>(())
+    }
+    .await;
+    if unreported_bytes > 0 {
+        downloader.progress.report(&ProgressEvent::BytesReceived {
+            gamebanana_id,
+            bytes: unreported_bytes,
+        });
+    }
+    result?;
+    part_file.flush().await?;
+    part_file.sync_all().await?;
 
     Ok(())
 }
 
-pub fn download_maps() -> Result<(), Box<dyn Error>> {
+// Shared by every download task.
+struct Downloader {
+    source: Arc<dyn ModSource>,
+    scheduler: Scheduler,
+    mirrors: Mirrors,
+    progress: Arc<dyn ProgressReporter>,
+    budget: Mutex<Budget>,
+    // Files of unknown size download one at a time under a disk budget, see unknown_size_permit.
+    unknown_sizes: Semaphore,
+}
+
+impl Downloader {
+    // Waits until a file of unknown size may start, or returns why it doesn't fit the budget.
+    // Its size is only known once it's downloaded, so the next one waits until it's counted.
+    async fn unknown_size_permit(
+        &self,
+        zip_path: &Path,
+    ) -> Result<Option<SemaphorePermit<'_>>, String> {
+        if !self.budget.lock().unwrap().is_limited() {
+            return Ok(None);
+        }
+        let permit = self.unknown_sizes.acquire().await.unwrap();
+        self.budget.lock().unwrap().reserve(None, zip_path)?;
+        Ok(Some(permit))
+    }
+}
+
+// Downloads and verifies the file from one URL, leaving a verified file at part_path.
+// Returns the file's xxHash.
+async fn download_verified(
+    downloader: &Downloader,
+    gamebanana_id: u64,
+    attempt: u32,
+    url: &str,
+    file: &FileDetails,
+    part_path: &Path,
+    quarantine_path: &Path,
+) -> Result<u64, DownloadError> {
+    let _permit = downloader.scheduler.acquire(url).await;
+    downloader.progress.report(&ProgressEvent::Started {
+        gamebanana_id,
+        attempt,
+        url: url.to_string(),
+    });
+
+    download_to_part(downloader, gamebanana_id, url, file.size, part_path).await?;
+
+    let hash = {
+        let part_path = part_path.to_owned();
+        tokio::task::spawn_blocking(move || checksum::xx_hash_file(&part_path))
+            .await
+            .map_err(io::Error::other)??
+    };
+    if !checksum::matches_any(&file.xx_hash, hash) {
+        // Keep the bad download for inspection, the zip path stays empty so it's downloaded again.
+        if let Some(quarantine_dir) = quarantine_path.parent() {
+            fs::create_dir_all(quarantine_dir).await?;
+        }
+        fs::rename(part_path, quarantine_path).await?;
+        remove_part(part_path).await?;
+        return Err(DownloadError::ChecksumMismatch {
+            got: hash,
+            expected: file.xx_hash.clone(),
+        });
+    }
+
+    Ok(hash)
+}
+
+async fn download_file(
+    downloader: &Downloader,
+    gamebanana_id: u64,
+    attempt: u32,
+    file: &FileDetails,
+    zip_path: &Path,
+    quarantine_path: &Path,
+) -> Result<u64, DownloadError> {
+    if let Some(zip_dir) = zip_path.parent() {
+        fs::create_dir_all(zip_dir).await?;
+    }
+
+    let part_path = zip_path.with_extension("zip.part");
+    let mut errors = vec![];
+    for (mirror, url) in downloader.mirrors.candidates(&file.url) {
+        let result = download_verified(
+            downloader,
+            gamebanana_id,
+            attempt,
+            &url,
+            file,
+            &part_path,
+            quarantine_path,
+        )
+        .await;
+        downloader
+            .mirrors
+            .record(&mirror, result.as_ref().map(|_| ()));
+        match result {
+            Ok(hash) => {
+                // Rename is atomic, so the zip path only ever holds complete downloads.
+                fs::rename(&part_path, zip_path).await?;
+                remove_part(&part_path).await?;
+                return Ok(hash);
+            }
+            Err(err) => errors.push(err),
+        }
+    }
+
+    // Retry if any mirror might succeed later, even when another one failed permanently.
+    let transient = errors
+        .iter()
+        .rposition(|err| err.kind() == ErrorKind::Transient);
+    match transient {
+        Some(index) => Err(errors.swap_remove(index)),
+        None => Err(errors.pop().unwrap_or(DownloadError::NoFiles)),
+    }
+}
+
+// Mods with a readable zip, or whose zip was extracted to the store and pruned.
+fn read_downloaded_ids(paths: &PathsConfig) -> Result<HashSet<u64>, Box<dyn Error>> {
     let mut downloaded_ids = HashSet::new();
-    for file in std::fs::read_dir("mods")? {
+    let store_index = StoreIndex::load(&paths.store_index())?;
+    for &gamebanana_id in store_index.mods.keys() {
+        // A zip which is still there has to be readable like any other, below.
+        if store_index.is_pruned(paths, gamebanana_id, &format!("{}.zip", gamebanana_id)) {
+            downloaded_ids.insert(gamebanana_id);
+        }
+    }
+
+    for file in std::fs::read_dir(&paths.mods_dir)? {
         let file = file?;
         if file.path().is_file() && file.path().extension() == Some(OsStr::new("zip")) {
             let id = file
@@ -62,96 +401,985 @@ pub fn download_maps() -> Result<(), Box<dyn Error>> {
             }
         }
     }
+    Ok(downloaded_ids)
+}
 
-    let mut number_of_mods = 0;
+// Size and xxHash of a downloaded zip, a pruned zip only has the size it had when it was extracted.
+fn existing_zip(
+    paths: &PathsConfig,
+    store_index: &StoreIndex,
+    gamebanana_id: u64,
+) -> io::Result<(Option<u64>, Option<u64>)> {
+    let zip_path = paths.mod_zip(gamebanana_id);
+    if zip_path.is_file() {
+        let size = std::fs::metadata(&zip_path)?.len();
+        return Ok((Some(size), Some(checksum::xx_hash_file(&zip_path)?)));
+    }
+    let extracted = store_index.extracted(gamebanana_id, &format!("{}.zip", gamebanana_id));
+    Ok((extracted.map(|extracted| extracted.size), None))
+}
 
-    let rt = Runtime::new()?;
-    rt.block_on(async {
-        if !fs::try_exists("mods").await? {
-            let _ = fs::create_dir("mods").await;
+#[derive(Debug, Clone, Default)]
+pub struct DownloadOptions {
+    // Re-fetch the mod database and re-download mods which have a newer file.
+    pub refresh: bool,
+    // List what would be downloaded with the estimated size, without downloading or writing files.
+    pub dry_run: bool,
+    // Also download the mods which the downloaded mods depend on in their everest.yaml.
+    pub dependencies: bool,
+    // Append every progress event to this file as JSON lines, see progress::JsonLinesProgress.
+    pub event_log: Option<PathBuf>,
+    // Only download the mods which failed in earlier runs, see state::DownloadState.
+    pub retry_failed: bool,
+    // Parse the maps of each zip as soon as it's downloaded, see pipeline::Pipeline.
+    pub process: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DownloadKind {
+    New,
+    Update,
+    Dependency,
+    // A newer file of a downloaded dependency, found when refreshing.
+    DependencyUpdate,
+    // A file kept in the per-mod history, see download::history.
+    History,
+}
+
+// One file to download, the latest file of a mod unless it's a history download.
+struct DownloadJob {
+    mod_detail: ModDetail,
+    file: Option<FileDetails>,
+    kind: DownloadKind,
+    zip_path: PathBuf,
+    quarantine_path: PathBuf,
+    // Released when the download fails.
+    reservation: Reservation,
+}
+
+impl DownloadJob {
+    fn latest(paths: &PathsConfig, mod_detail: ModDetail, kind: DownloadKind) -> DownloadJob {
+        DownloadJob {
+            file: mod_detail.latest_file().cloned(),
+            zip_path: paths.mod_zip(mod_detail.gamebanana_id),
+            quarantine_path: paths.quarantined_zip(mod_detail.gamebanana_id),
+            mod_detail,
+            kind,
+            reservation: Reservation::default(),
         }
+    }
 
-        let client = reqwest::ClientBuilder::new()
-            .timeout(Duration::from_secs(3 * 60))
-            .build()?;
+    fn history(paths: &PathsConfig, mod_detail: &ModDetail, file: &FileDetails) -> DownloadJob {
+        let file_id = history::file_id(file);
+        DownloadJob {
+            mod_detail: mod_detail.clone(),
+            file: Some(file.clone()),
+            kind: DownloadKind::History,
+            zip_path: paths.mod_file_zip(mod_detail.gamebanana_id, &file_id),
+            quarantine_path: paths.quarantined_file_zip(mod_detail.gamebanana_id, &file_id),
+            reservation: Reservation::default(),
+        }
+    }
+}
+
+// Why a download task gave up on its job.
+enum Stopped {
+    Failed(DownloadFailure),
+    // A file of unknown size which found the disk budget used up when it was about to start.
+    OverBudget(String),
+}
+
+#[derive(Debug, Default)]
+struct RefreshReport {
+    added: Vec<u64>,
+    updated: Vec<u64>,
+    // Downloaded mods which left the database, their zips are kept.
+    removed: Vec<u64>,
+    // Downloaded mods which are neither selected nor a followed dependency, their zips are kept.
+    deselected: Vec<u64>,
+    dependencies: Vec<u64>,
+    history_files: usize,
+    // Skipped because they didn't fit the disk budget.
+    over_budget: Vec<u64>,
+}
+
+// Written to the output directory after every run.
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadFailure {
+    pub gamebanana_id: u64,
+    pub name: String,
+    pub url: Option<String>,
+    pub kind: ErrorKind,
+    // Error of every attempt, the last one is why the download was given up.
+    pub attempts: Vec<String>,
+}
+
+// Zips downloaded before the manifest existed are adopted if they match the latest file's checksum.
+// Without checksums the size has to match, and without either the zip is downloaded again.
+// Pruned zips can only be compared by the size they had when they were extracted.
+fn needs_update(
+    manifest: &mut Manifest,
+    store_index: &StoreIndex,
+    mod_detail: &ModDetail,
+    zip_path: &Path,
+) -> Result<bool, Box<dyn Error>> {
+    let Some(latest_file) = mod_detail.latest_file() else {
+        return Ok(false);
+    };
 
-        let mods_list = if fs::try_exists("mods/mods_list.yaml").await? {
-            fs::read_to_string("mods/mods_list.yaml").await?
+    if manifest.mods.contains_key(&mod_detail.gamebanana_id) {
+        return Ok(!manifest.is_current(mod_detail.gamebanana_id, latest_file));
+    }
+
+    let gamebanana_id = mod_detail.gamebanana_id;
+    let is_latest = if !zip_path.is_file() {
+        let extracted = store_index.extracted(gamebanana_id, &format!("{}.zip", gamebanana_id));
+        latest_file.size.is_some() && extracted.map(|zip| zip.size) == latest_file.size
+    } else if !latest_file.xx_hash.is_empty() {
+        checksum::matches_any(&latest_file.xx_hash, checksum::xx_hash_file(zip_path)?)
+    } else if let Some(size) = latest_file.size {
+        std::fs::metadata(zip_path)?.len() == size
+    } else {
+        false
+    };
+    if is_latest {
+        manifest
+            .mods
+            .insert(gamebanana_id, ManifestEntry::new(latest_file));
+        Ok(false)
+    } else {
+        Ok(true)
+    }
+}
+
+// Splits off the jobs which don't fit the disk budget, with the reason, before anything is downloaded.
+fn within_budget(
+    budget: &mut Budget,
+    queue: Vec<DownloadJob>,
+) -> (Vec<DownloadJob>, Vec<(DownloadJob, String)>) {
+    let mut jobs = vec![];
+    let mut skipped = vec![];
+    for mut job in queue {
+        let size = job.file.as_ref().and_then(|file| file.size);
+        match budget.reserve(size, &job.zip_path) {
+            Ok(reservation) => {
+                job.reservation = reservation;
+                jobs.push(job);
+            }
+            Err(reason) => skipped.push((job, reason)),
+        }
+    }
+    (jobs, skipped)
+}
+
+fn print_dry_run(queue: &[DownloadJob]) {
+    let mut total_size = 0;
+    let mut unknown_sizes = 0;
+    for job in queue {
+        let size = job.file.as_ref().and_then(|file| file.size);
+        total_size += size.unwrap_or_default();
+        if size.is_none() {
+            unknown_sizes += 1;
+        }
+
+        let modules = job
+            .mod_detail
+            .everest_modules
+            .iter()
+            .map(|module| format!("{} {}", module.name, module.version))
+            .collect::<Vec<_>>();
+        println!(
+            "{} {}({}){} - {} - {}",
+            match job.kind {
+                DownloadKind::New => "New",
+                DownloadKind::Update => "Update",
+                DownloadKind::Dependency => "Dependency",
+                DownloadKind::DependencyUpdate => "Dependency update",
+                DownloadKind::History => "History",
+            },
+            job.mod_detail.name,
+            job.mod_detail.gamebanana_id,
+            if modules.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", modules.join(", "))
+            },
+            job.file
+                .as_ref()
+                .map_or("no files", |file| file.url.as_str()),
+            size.map_or("unknown size".to_string(), format_size)
+        );
+    }
+
+    println!(
+        "{} files would be downloaded, {} in total{}.",
+        queue.len(),
+        format_size(total_size),
+        if unknown_sizes > 0 {
+            format!(" ({} of unknown size)", unknown_sizes)
         } else {
-            let mods_list = client
-                .get("https://maddie480.ovh/celeste/mod_search_database.yaml")
-                .send()
-                .await?
-                .text()
-                .await?;
-            fs::write("mods/mods_list.yaml", &mods_list).await?;
-            mods_list
+            String::new()
+        }
+    );
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+// Reads the cached copy, or fetches the file and caches it. The fetch only runs when it's awaited.
+async fn fetch_cached(
+    fetch: SourceFuture<'_, Result<String, DownloadError>>,
+    path: &Path,
+    options: &DownloadOptions,
+) -> Result<String, Box<dyn Error>> {
+    if !options.refresh && fs::try_exists(path).await? {
+        return Ok(fs::read_to_string(path).await?);
+    }
+
+    let text = fetch.await?;
+    if !options.dry_run {
+        fs::write(path, &text).await?;
+    }
+    Ok(text)
+}
+
+// Jobs for the mods which failed in earlier runs, including dependencies and unselected mods.
+fn failed_jobs(
+    paths: &PathsConfig,
+    state: &mut DownloadState,
+    run_report: &mut RunReport,
+    all_mods: &[ModDetail],
+    downloaded_ids: &HashSet<u64>,
+) -> Vec<DownloadJob> {
+    let mods_by_id = all_mods
+        .iter()
+        .map(|mod_detail| (mod_detail.gamebanana_id, mod_detail))
+        .collect::<HashMap<_, _>>();
+
+    let mut queue = vec![];
+    for gamebanana_id in state.failed_ids() {
+        let Some(mod_detail) = mods_by_id.get(&gamebanana_id) else {
+            state.record_skip(gamebanana_id, "", "Not in the mod database");
+            run_report.skipped(gamebanana_id, "", "Not in the mod database");
+            continue;
+        };
+        let kind = if downloaded_ids.contains(&gamebanana_id) {
+            DownloadKind::Update
+        } else {
+            DownloadKind::New
         };
+        queue.push(DownloadJob::latest(paths, (*mod_detail).clone(), kind));
+    }
+    queue
+}
+
+// State of one download_maps call, shared between the selected mods and their dependencies.
+struct DownloadRun<'a> {
+    paths: &'a PathsConfig,
+    zip_limits: &'a ZipLimits,
+    // The latest file of each selected mod is also kept in its history, see history::link_file.
+    all_files: bool,
+    // Downloaded dependencies are updated too, see download_dependencies.
+    refresh: bool,
+    downloader: Arc<Downloader>,
+    retry_policy: RetryPolicy,
+    manifest: Manifest,
+    state: DownloadState,
+    // Owned by download_maps_with, which writes it when the run stops with an error too.
+    run_report: &'a mut RunReport,
+    pipeline: Option<Pipeline>,
+    report: RefreshReport,
+    failures: Vec<DownloadFailure>,
+}
+
+impl DownloadRun<'_> {
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        self.manifest.save(&self.paths.manifest())?;
+        self.state.save(&self.paths.download_state())
+    }
+
+    fn skip_over_budget(&mut self, job: &DownloadJob, reason: &str) {
+        let gamebanana_id = job.mod_detail.gamebanana_id;
+        println!(
+            "Skipped {}({}) - {}",
+            job.mod_detail.name, gamebanana_id, reason
+        );
+        if job.kind != DownloadKind::History {
+            self.state
+                .record_skip(gamebanana_id, &job.mod_detail.name, reason);
+            self.run_report
+                .skipped(gamebanana_id, &job.mod_detail.name, reason);
+        }
+        self.report.over_budget.push(gamebanana_id);
+    }
+
+    async fn download_queue(&mut self, queue: Vec<DownloadJob>) -> Result<(), Box<dyn Error>> {
+        let (queue, skipped) = within_budget(&mut self.downloader.budget.lock().unwrap(), queue);
+        for (job, reason) in skipped {
+            self.skip_over_budget(&job, &reason);
+        }
+
+        let progress = &self.downloader.progress;
+        for job in &queue {
+            progress.report(&ProgressEvent::Queued {
+                gamebanana_id: job.mod_detail.gamebanana_id,
+                name: job.mod_detail.name.clone(),
+                url: job.file.as_ref().map(|file| file.url.clone()),
+                size: job.file.as_ref().and_then(|file| file.size),
+            });
+        }
+
+        let mut downloads = JoinSet::new();
+        for job in queue {
+            let downloader = self.downloader.clone();
+            let retry_policy = self.retry_policy.clone();
+            downloads.spawn(async move {
+                let started = Instant::now();
+                let mod_detail = &job.mod_detail;
+                let gamebanana_id = mod_detail.gamebanana_id;
+                let mut attempts = vec![];
+                loop {
+                    let attempt = attempts.len() as u32 + 1;
+                    let unknown_size = job.file.as_ref().is_some_and(|file| file.size.is_none());
+                    let _unknown_size_permit = if unknown_size {
+                        match downloader.unknown_size_permit(&job.zip_path).await {
+                            Ok(permit) => permit,
+                            Err(reason) => {
+                                downloader.progress.report(&ProgressEvent::Failed {
+                                    gamebanana_id,
+                                    name: mod_detail.name.clone(),
+                                    error: reason.clone(),
+                                    attempts: attempts.len() as u32,
+                                });
+                                return Err((job, Stopped::OverBudget(reason), started.elapsed()));
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                    let result = match &job.file {
+                        Some(file) => {
+                            download_file(
+                                &downloader,
+                                gamebanana_id,
+                                attempt,
+                                file,
+                                &job.zip_path,
+                                &job.quarantine_path,
+                            )
+                            .await
+                        }
+                        None => Err(DownloadError::NoFiles),
+                    };
+                    let err = match result {
+                        Ok(hash) => {
+                            if unknown_size {
+                                let size = fs::metadata(&job.zip_path)
+                                    .await
+                                    .map_or(0, |metadata| metadata.len());
+                                downloader.budget.lock().unwrap().downloaded(size);
+                            }
+                            downloader.progress.report(&ProgressEvent::Finished {
+                                gamebanana_id,
+                                name: mod_detail.name.clone(),
+                                path: job.zip_path.display().to_string(),
+                                attempts: attempt,
+                            });
+                            return Ok((job, attempt, hash, started.elapsed()));
+                        }
+                        Err(err) => err,
+                    };
+
+                    let delay = retry_policy.next_delay(attempt, &err);
+                    attempts.push(err.to_string());
+
+                    let Some(delay) = delay else {
+                        downloader.progress.report(&ProgressEvent::Failed {
+                            gamebanana_id,
+                            name: mod_detail.name.clone(),
+                            error: err.to_string(),
+                            attempts: attempt,
+                        });
+                        let failure = DownloadFailure {
+                            gamebanana_id: mod_detail.gamebanana_id,
+                            name: mod_detail.name.clone(),
+                            url: job.file.as_ref().map(|file| file.url.clone()),
+                            kind: err.kind(),
+                            attempts,
+                        };
+                        return Err((job, Stopped::Failed(failure), started.elapsed()));
+                    };
+                    downloader.progress.report(&ProgressEvent::Retried {
+                        gamebanana_id,
+                        attempt,
+                        error: err.to_string(),
+                        delay_secs: delay.as_secs_f64(),
+                    });
+                    tokio::time::sleep(delay).await;
+                }
+            });
+        }
+
+        let mut unsaved_downloads = 0;
+        while let Some(download) = downloads.join_next().await {
+            let (job, attempts, hash, duration) = match download? {
+                Ok(download) => download,
+                Err((job, stopped, duration)) => {
+                    self.downloader
+                        .budget
+                        .lock()
+                        .unwrap()
+                        .release(job.reservation);
+                    let failure = match stopped {
+                        Stopped::Failed(failure) => failure,
+                        Stopped::OverBudget(reason) => {
+                            self.skip_over_budget(&job, &reason);
+                            continue;
+                        }
+                    };
+                    // History files are tracked in their own files.yaml, the state is about the latest file.
+                    if job.kind != DownloadKind::History {
+                        self.state.record_failure(&failure);
+                        self.run_report.failed(&failure, duration);
+                    }
+                    self.failures.push(failure);
+                    continue;
+                }
+            };
+
+            let gamebanana_id = job.mod_detail.gamebanana_id;
+            let size = fs::metadata(&job.zip_path).await?.len();
+            let outcome = match job.kind {
+                DownloadKind::New => {
+                    self.report.added.push(gamebanana_id);
+                    Outcome::Downloaded
+                }
+                DownloadKind::Update | DownloadKind::DependencyUpdate => {
+                    self.report.updated.push(gamebanana_id);
+                    Outcome::Updated
+                }
+                DownloadKind::Dependency => {
+                    self.report.dependencies.push(gamebanana_id);
+                    Outcome::Downloaded
+                }
+                DownloadKind::History => {
+                    self.report.history_files += 1;
+                    self.run_report.history_file(size);
+                    if let Some(file) = &job.file {
+                        FileHistory::record(&self.paths.mod_file_history(gamebanana_id), file)?;
+                    }
+                    continue;
+                }
+            };
+            self.run_report.downloaded(
+                gamebanana_id,
+                &job.mod_detail.name,
+                outcome,
+                size,
+                duration,
+            );
+            if let Some(file) = &job.file {
+                self.manifest
+                    .mods
+                    .insert(gamebanana_id, ManifestEntry::new(file));
+                let dependency = matches!(
+                    job.kind,
+                    DownloadKind::Dependency | DownloadKind::DependencyUpdate
+                );
+                if self.all_files && !dependency {
+                    history::link_file(self.paths, gamebanana_id, file)?;
+                }
+                self.state.record_success(
+                    gamebanana_id,
+                    &job.mod_detail.name,
+                    file,
+                    size,
+                    hash,
+                    attempts,
+                );
+            }
+            if let Some(pipeline) = &mut self.pipeline {
+                pipeline.submit(gamebanana_id, job.mod_detail.name.clone(), job.zip_path);
+            }
+
+            // Saved regularly so finished downloads are recorded even if the run is interrupted.
+            unsaved_downloads += 1;
+            if unsaved_downloads == MANIFEST_SAVE_INTERVAL {
+                self.save()?;
+                unsaved_downloads = 0;
+            }
+        }
+        self.save()?;
+
+        Ok(())
+    }
+
+    // Follows everest.yaml dependencies from the given mods until every reachable mod was visited.
+    async fn download_dependencies(
+        &mut self,
+        mods_list: &[ModDetail],
+        module_index: &ModuleIndex,
+        roots: Vec<u64>,
+    ) -> Result<DependencyGraph, Box<dyn Error>> {
+        let mods_by_id = mods_list
+            .iter()
+            .map(|mod_detail| (mod_detail.gamebanana_id, mod_detail))
+            .collect::<HashMap<_, _>>();
+
+        // Pruned mods are read from the store.
+        let mod_files = ModFiles::new(self.paths, self.zip_limits)?;
+        let store_index = StoreIndex::load(&self.paths.store_index())?;
+        let mut graph = DependencyGraph::default();
+        let mut visited = roots.iter().copied().collect::<HashSet<_>>();
+        let mut pending = roots;
+        while !pending.is_empty() {
+            let mut queue = vec![];
+            for gamebanana_id in pending {
+                for dependency_id in graph.add_mod(gamebanana_id, &mod_files, module_index) {
+                    if !visited.insert(dependency_id) {
+                        continue;
+                    }
+                    let mod_detail = mods_by_id.get(&dependency_id);
+                    if mod_files.is_downloaded(dependency_id) {
+                        // Already downloaded, but its own dependencies still need to be followed.
+                        let update = match mod_detail {
+                            Some(mod_detail) if self.refresh => needs_update(
+                                &mut self.manifest,
+                                &store_index,
+                                mod_detail,
+                                &self.paths.mod_zip(dependency_id),
+                            )?
+                            .then_some((*mod_detail, DownloadKind::DependencyUpdate)),
+                            _ => None,
+                        };
+                        queue.push((update, dependency_id));
+                    } else if let Some(mod_detail) = mod_detail {
+                        queue.push((Some((*mod_detail, DownloadKind::Dependency)), dependency_id));
+                    } else {
+                        graph.missing_mods.insert(dependency_id);
+                    }
+                }
+            }
+
+            pending = queue
+                .iter()
+                .map(|(_, gamebanana_id)| *gamebanana_id)
+                .collect();
+            let downloads = queue
+                .into_iter()
+                .filter_map(|(download, _)| {
+                    let (mod_detail, kind) = download?;
+                    Some(DownloadJob::latest(self.paths, mod_detail.clone(), kind))
+                })
+                .collect();
+            self.download_queue(downloads).await?;
+        }
+
+        Ok(graph)
+    }
+}
+
+// The mods of the last download, from the cached databases. Without a cached everest_update.yaml
+// that's only the mod database.
+pub fn cached_mods(config: &Config) -> Result<Vec<ModDetail>, Box<dyn Error>> {
+    let paths = &config.paths;
+    let mods_list = serde_yaml::from_str(&std::fs::read_to_string(paths.mods_list())?)?;
+    if !paths.everest_update().is_file() {
+        return Ok(mods_list);
+    }
+    let module_index =
+        ModuleIndex::from_everest_update(&std::fs::read_to_string(paths.everest_update())?)?;
+    Ok(module_index.apply(mods_list, config.download.index))
+}
+
+// Downloads from download.source_dir when set, otherwise over HTTP. Reports progress on the
+// terminal, and to the event log when one is given. Returns how many mods failed.
+pub fn download_maps(config: &Config, options: &DownloadOptions) -> Result<usize, Box<dyn Error>> {
+    let source: Arc<dyn ModSource> = match &config.download.source_dir {
+        Some(source_dir) => Arc::new(LocalSource::new(source_dir.clone())),
+        None => Arc::new(HttpSource::new(&config.download)?),
+    };
+
+    let mut reporters: Vec<Box<dyn ProgressReporter>> = vec![Box::new(TerminalProgress::default())];
+    if let Some(event_log) = &options.event_log {
+        let event_log = JsonLinesProgress::create(event_log)
+            .map_err(|err| format!("Couldn't open {}: {}", event_log.display(), err))?;
+        reporters.push(Box::new(event_log));
+    }
+    download_maps_with(config, options, source, Arc::new(reporters))
+}
+
+pub fn download_maps_with(
+    config: &Config,
+    options: &DownloadOptions,
+    source: Arc<dyn ModSource>,
+    progress: Arc<dyn ProgressReporter>,
+) -> Result<usize, Box<dyn Error>> {
+    let paths = &config.paths;
+    let download_config = &config.download;
+
+    if !paths.mods_dir.exists() {
+        std::fs::create_dir_all(&paths.mods_dir)?;
+    }
+
+    let downloaded_ids = read_downloaded_ids(paths)?;
+    let mut manifest = Manifest::load(&paths.manifest())?;
+    let mut state = DownloadState::load(&paths.download_state())?;
+    let mut run_report = RunReport::default();
 
-        let mods_list: Vec<ModDetail> = serde_yaml::from_str(&mods_list)?;
-        let mut mods_list_iter = mods_list.into_iter();
-
-        const DOWNLOADS_AT_TIME: usize = 100;
-        let mut started_all_downloads = false;
-        while !started_all_downloads {
-            let mut downloads = vec![];
-            while downloads.len() < DOWNLOADS_AT_TIME {
-                let Some(mod_detail) = mods_list_iter.next() else {
-                    started_all_downloads = true;
-                    break;
+    // Selected mods and the dependencies which were followed.
+    let mut wanted_ids = HashSet::new();
+
+    let rt = Runtime::new()?;
+    let run = rt.block_on(async {
+        let mods_list = fetch_cached(source.mod_database(), &paths.mods_list(), options).await?;
+        let mut all_mods: Vec<ModDetail> = serde_yaml::from_str(&mods_list)?;
+        // Both indexes are merged when everest_update.yaml is needed anyway.
+        let module_index =
+            if options.dependencies || download_config.index == ModIndex::EverestUpdate {
+                let everest_update =
+                    fetch_cached(source.everest_update(), &paths.everest_update(), options).await?;
+                Some(ModuleIndex::from_everest_update(&everest_update)?)
+            } else {
+                None
+            };
+        if let Some(module_index) = &module_index {
+            all_mods = module_index.apply(all_mods, download_config.index);
+        }
+        let selection = Selection::new(download_config)?;
+        let mods_list = all_mods
+            .iter()
+            .filter(|mod_detail| selection.selects(mod_detail))
+            .cloned()
+            .collect::<Vec<_>>();
+        let selected_ids = mods_list
+            .iter()
+            .map(|mod_detail| mod_detail.gamebanana_id)
+            .collect::<Vec<_>>();
+
+        wanted_ids.extend(selected_ids.iter().copied());
+
+        // Downloaded mods, those which are deselected are only known after following dependencies.
+        let mut known_ids = downloaded_ids
+            .iter()
+            .chain(manifest.mods.keys())
+            .copied()
+            .collect::<Vec<_>>();
+        known_ids.sort();
+        known_ids.dedup();
+        let mut report = RefreshReport::default();
+        if options.refresh {
+            let database_ids = all_mods
+                .iter()
+                .map(|mod_detail| mod_detail.gamebanana_id)
+                .collect::<HashSet<_>>();
+            let (in_database, removed): (Vec<_>, Vec<_>) = known_ids
+                .into_iter()
+                .partition(|id| database_ids.contains(id));
+            for gamebanana_id in &removed {
+                state.record_skip(*gamebanana_id, "", "Not in the mod database");
+            }
+            known_ids = in_database;
+            report.removed = removed;
+        }
+        // Selected mods and the dependencies which were followed, unlike wanted_ids not only the
+        // retried ones.
+        let mut followed_ids = selected_ids.iter().copied().collect::<HashSet<_>>();
+
+        let mut queue = vec![];
+        if options.retry_failed {
+            queue = failed_jobs(
+                paths,
+                &mut state,
+                &mut run_report,
+                &all_mods,
+                &downloaded_ids,
+            );
+            // Only the retried mods are counted at the end.
+            wanted_ids = queue
+                .iter()
+                .map(|job| job.mod_detail.gamebanana_id)
+                .collect();
+        } else {
+            let store_index = StoreIndex::load(&paths.store_index())?;
+            for mod_detail in mods_list {
+                let gamebanana_id = mod_detail.gamebanana_id;
+                let zip_path = paths.mod_zip(gamebanana_id);
+                let kind = if !downloaded_ids.contains(&gamebanana_id) {
+                    Some(DownloadKind::New)
+                } else if options.refresh
+                    && needs_update(&mut manifest, &store_index, &mod_detail, &zip_path)?
+                {
+                    Some(DownloadKind::Update)
+                } else {
+                    None
                 };
 
-                if mod_detail.category_name == "Maps" {
-                    number_of_mods += 1;
-                    if !downloaded_ids.contains(&mod_detail.gamebanana_id) {
-                        let client = client.clone();
-                        downloads.push(tokio::spawn(async move {
-                            for i in 0..3 {
-                                if let Err(err) = download_mod(&client, &mod_detail).await {
-                                    if i == 2 {
-                                        eprintln!(
-                                            "Mod {}({}) - Download error - {}",
-                                            mod_detail.name, mod_detail.gamebanana_id, err
-                                        );
-                                    }
-                                } else {
-                                    println!(
-                                        "Downloaded mod {}({})",
-                                        mod_detail.name, mod_detail.gamebanana_id
-                                    );
-                                    break;
-                                }
+                if download_config.all_files {
+                    let latest_file = mod_detail.latest_file();
+                    let latest_is_current = kind.is_none()
+                        && latest_file.is_some_and(|file| manifest.is_current(gamebanana_id, file));
+                    // Newest first, older files past budget.keep_versions would be pruned anyway.
+                    let mut files = mod_detail.files.iter().collect::<Vec<_>>();
+                    files.sort_by_key(|file| std::cmp::Reverse(file.created_date));
+                    files.truncate(config.budget.keep_versions.unwrap_or(usize::MAX));
+                    for file in files {
+                        let job = DownloadJob::history(paths, &mod_detail, file);
+                        let stored = store_index.contains(
+                            gamebanana_id,
+                            &format!("{}/{}.zip", gamebanana_id, history::file_id(file)),
+                        );
+                        if job.zip_path.is_file() || stored {
+                            continue;
+                        }
+                        // The latest file is linked from mods/<id>.zip rather than downloaded twice,
+                        // once it's downloaded when it's queued.
+                        if latest_file.is_some_and(|latest_file| latest_file.url == file.url) {
+                            if kind.is_some() {
+                                continue;
                             }
-                        }));
+                            if latest_is_current
+                                && !options.dry_run
+                                && history::link_file(paths, gamebanana_id, file)?
+                            {
+                                continue;
+                            }
+                        }
+                        queue.push(job);
+                    }
+                }
+
+                match kind {
+                    Some(kind) => queue.push(DownloadJob::latest(paths, mod_detail, kind)),
+                    None => {
+                        if !state.mods.contains_key(&gamebanana_id) {
+                            let (size, xx_hash) = existing_zip(paths, &store_index, gamebanana_id)?;
+                            let url = manifest
+                                .mods
+                                .get(&gamebanana_id)
+                                .map(|entry| entry.url.as_str());
+                            state.record_downloaded_before(
+                                gamebanana_id,
+                                &mod_detail.name,
+                                url,
+                                size,
+                                xx_hash,
+                            );
+                        }
+                        state.record_skip(gamebanana_id, &mod_detail.name, "Already downloaded");
+                        run_report.skipped(gamebanana_id, &mod_detail.name, "Already downloaded");
                     }
                 }
             }
+        }
 
-            for download in downloads {
-                download.await?;
+        let mut budget = Budget::new(&config.budget, paths)?;
+        if options.dry_run {
+            let (queue, skipped) = within_budget(&mut budget, queue);
+            for (job, reason) in skipped {
+                println!(
+                    "Skipped {}({}) - {}",
+                    job.mod_detail.name, job.mod_detail.gamebanana_id, reason
+                );
             }
+            print_dry_run(&queue);
+            return Ok(None);
         }
 
-        Ok::<(), Box<dyn Error>>(())
-    })?;
+        let pipeline = if options.process {
+            std::fs::create_dir_all(&paths.output_dir)?;
+            Some(Pipeline::new(
+                &paths.map_statistics(),
+                download_config.process_workers,
+                &config.zip,
+            )?)
+        } else {
+            None
+        };
 
-    let mut number_of_downloaded_mods = 0;
-    for file in std::fs::read_dir("mods")? {
-        let file = file?;
-        if file.path().is_file() && file.path().extension() == Some(OsStr::new("zip")) {
-            let file = zip::ZipArchive::new(std::fs::File::open(file.path())?);
-            if let Ok(file) = file {
-                if !file.is_empty() {
-                    number_of_downloaded_mods += 1;
+        let mut run = DownloadRun {
+            paths,
+            zip_limits: &config.zip,
+            all_files: download_config.all_files,
+            refresh: options.refresh,
+            downloader: Arc::new(Downloader {
+                source,
+                scheduler: Scheduler::new(download_config),
+                mirrors: Mirrors::new(download_config),
+                progress,
+                budget: Mutex::new(budget),
+                unknown_sizes: Semaphore::new(1),
+            }),
+            retry_policy: RetryPolicy::new(download_config),
+            manifest,
+            state,
+            run_report: &mut run_report,
+            pipeline,
+            report,
+            failures: vec![],
+        };
+        run.download_queue(queue).await?;
+
+        if let Some(module_index) = module_index.as_ref().filter(|_| options.dependencies) {
+            let graph = run
+                .download_dependencies(&all_mods, module_index, selected_ids)
+                .await?;
+            wanted_ids.extend(graph.mods.keys().copied());
+            followed_ids.extend(graph.mods.keys().copied());
+
+            std::fs::create_dir_all(&paths.output_dir)?;
+            std::fs::write(
+                paths.dependency_graph(),
+                serde_json::to_string_pretty(&graph)?,
+            )?;
+            println!(
+                "Dependencies: {} downloaded, {} modules and {} mods missing, see {}.",
+                run.report.dependencies.len(),
+                graph.missing_modules.len(),
+                graph.missing_mods.len(),
+                paths.dependency_graph().display()
+            );
+        }
+
+        if options.refresh {
+            let deselected = known_ids
+                .into_iter()
+                .filter(|id| !followed_ids.contains(id))
+                .collect::<Vec<_>>();
+            for gamebanana_id in &deselected {
+                run.state
+                    .record_skip(*gamebanana_id, "", "No longer selected");
+            }
+            run.report.deselected = deselected;
+            run.save()?;
+        }
+
+        if let Some(keep_versions) = config.budget.keep_versions {
+            let pruned = budget::prune_old_versions(paths, keep_versions)?;
+            if pruned.files > 0 {
+                println!(
+                    "Pruned {} old files, {} freed.",
+                    pruned.files,
+                    format_size(pruned.bytes)
+                );
+            }
+        }
+
+        if let Some(pipeline) = run.pipeline.take() {
+            let report = pipeline.finish().await?;
+            println!(
+                "Processed {} zips, {} maps ({} errors), see {}.",
+                report.zips,
+                report.maps,
+                report.errors,
+                paths.map_statistics().display()
+            );
+        }
+
+        run.failures.sort_by_key(|failure| failure.gamebanana_id);
+        std::fs::create_dir_all(&paths.output_dir)?;
+        std::fs::write(
+            paths.download_failures(),
+            serde_json::to_string_pretty(&run.failures)?,
+        )?;
+
+        let mirror_health = run.downloader.mirrors.health();
+        for (mirror, health) in &mirror_health {
+            println!(
+                "Mirror {}: {} succeeded, {} failed",
+                mirror, health.successes, health.failures
+            );
+        }
+        std::fs::write(
+            paths.mirror_health(),
+            serde_json::to_string_pretty(&mirror_health)?,
+        )?;
+
+        Ok::<_, Box<dyn Error>>(Some(run))
+    });
+
+    let run = match run {
+        Ok(run) => run,
+        Err(err) => {
+            // With what the run did until it stopped.
+            if !options.dry_run {
+                run_report.error = Some(err.to_string());
+                let run_report_path = paths.run_report(run_report.started_ms());
+                match run_report.save(&run_report_path) {
+                    Ok(()) => eprintln!("Run report written to {}.", run_report_path.display()),
+                    Err(save_err) => eprintln!("Couldn't write the run report: {}", save_err),
                 }
             }
+            return Err(err);
+        }
+    };
+    let Some(run) = run else {
+        return Ok(0);
+    };
+    let run_report_path = paths.run_report(run.run_report.started_ms());
+    run.run_report.save(&run_report_path)?;
+    let report = &run.report;
+
+    let number_of_downloaded_mods = read_downloaded_ids(paths)?
+        .intersection(&wanted_ids)
+        .count();
+
+    if options.refresh {
+        println!(
+            "Refresh: {} added, {} updated, {} removed from the database, {} no longer selected.",
+            report.added.len(),
+            report.updated.len(),
+            report.removed.len(),
+            report.deselected.len()
+        );
+        for (label, ids) in [
+            ("Added", &report.added),
+            ("Updated", &report.updated),
+            ("Removed", &report.removed),
+            ("No longer selected", &report.deselected),
+        ] {
+            if !ids.is_empty() {
+                let ids = ids.iter().map(u64::to_string).collect::<Vec<_>>();
+                println!("{}: {}", label, ids.join(", "));
+            }
         }
     }
 
+    if !report.over_budget.is_empty() {
+        println!(
+            "{} files were skipped by the disk budget, {} used.",
+            report.over_budget.len(),
+            format_size(run.downloader.budget.lock().unwrap().used())
+        );
+    }
+    if report.history_files > 0 {
+        println!("{} older or extra files downloaded.", report.history_files);
+    }
     println!(
         "{} out of {} mods have been downloaded.",
-        number_of_downloaded_mods, number_of_mods
+        number_of_downloaded_mods,
+        wanted_ids.len()
     );
+    if !run.failures.is_empty() {
+        println!(
+            "{} mods failed, see {}.",
+            run.failures.len(),
+            paths.download_failures().display()
+        );
+    }
+    println!("Run report written to {}.", run_report_path.display());
 
-    Ok(())
+    Ok(run.failures.len())
 }
@@ -0,0 +1,273 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fs, io,
+    path::Path,
+};
+
+use super::{format_size, history::FileHistory};
+use crate::config::{BudgetConfig, PathsConfig};
+
+const MB: u64 = 1024 * 1024;
+
+// Files seen so far, so a file hard linked into mods/<id>/ (see history::link_file) counts once.
+#[derive(Debug, Default)]
+struct SeenFiles(HashSet<(u64, u64)>);
+
+impl SeenFiles {
+    #[cfg(unix)]
+    fn size(&mut self, metadata: &fs::Metadata) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+
+        if metadata.nlink() > 1 && !self.0.insert((metadata.dev(), metadata.ino())) {
+            return 0;
+        }
+        metadata.len()
+    }
+
+    #[cfg(not(unix))]
+    fn size(&mut self, metadata: &fs::Metadata) -> u64 {
+        metadata.len()
+    }
+}
+
+fn dir_size(dir: &Path, seen: &mut SeenFiles) -> io::Result<u64> {
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path(), seen)?
+        } else {
+            seen.size(&metadata)
+        };
+    }
+    Ok(size)
+}
+
+// Disk used by the corpus, the downloads in mods_dir and the extracted files in store_dir.
+pub fn disk_usage(paths: &PathsConfig) -> io::Result<u64> {
+    let mut seen = SeenFiles::default();
+    Ok(dir_size(&paths.mods_dir, &mut seen)? + dir_size(&paths.store_dir, &mut seen)?)
+}
+
+// Bytes used by the zips of each mod, mods/<id>.zip and every file in mods/<id>/.
+pub fn mod_usage(paths: &PathsConfig) -> Result<BTreeMap<u64, u64>, Box<dyn Error>> {
+    let mut usage = BTreeMap::new();
+    if !paths.mods_dir.is_dir() {
+        return Ok(usage);
+    }
+    let mut seen = SeenFiles::default();
+    for entry in fs::read_dir(&paths.mods_dir)? {
+        let path = entry?.path();
+        let (gamebanana_id, size) = if path.is_dir() {
+            (path.file_name(), dir_size(&path, &mut seen)?)
+        } else if path.extension().is_some_and(|extension| extension == "zip") {
+            (path.file_stem(), seen.size(&fs::metadata(&path)?))
+        } else {
+            continue;
+        };
+        // The quarantine and other files which aren't named after a mod.
+        let Some(gamebanana_id) = gamebanana_id
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        *usage.entry(gamebanana_id).or_default() += size;
+    }
+    Ok(usage)
+}
+
+// What reserving a file added to the budget, nothing for files of unknown size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reservation {
+    size: u64,
+    replaced: u64,
+}
+
+// Tracks the disk usage while a run downloads, so a file is only started when it fits.
+#[derive(Debug, Clone)]
+pub struct Budget {
+    max_total_size: Option<u64>,
+    max_file_size: Option<u64>,
+    used: u64,
+}
+
+impl Budget {
+    pub fn new(budget_config: &BudgetConfig, paths: &PathsConfig) -> io::Result<Budget> {
+        Ok(Budget {
+            max_total_size: budget_config.max_total_size_mb.map(|size| size * MB),
+            max_file_size: budget_config.max_file_size_mb.map(|size| size * MB),
+            used: disk_usage(paths)?,
+        })
+    }
+
+    // Reserves the size of a file replacing the zip at zip_path, or returns why it doesn't fit.
+    // Files of unknown size fit while the budget isn't used up, their size is added once
+    // downloaded, see downloaded. They're checked again right before they start, since the
+    // budget may be used up by then.
+    pub fn reserve(&mut self, size: Option<u64>, zip_path: &Path) -> Result<Reservation, String> {
+        let Some(size) = size else {
+            return match self.max_total_size {
+                Some(max_total_size) if self.used >= max_total_size => Err(format!(
+                    "Size unknown and the disk budget of {} is used up",
+                    format_size(max_total_size)
+                )),
+                _ => Ok(Reservation::default()),
+            };
+        };
+        if let Some(max_file_size) = self.max_file_size {
+            if size > max_file_size {
+                return Err(format!(
+                    "Over the file size limit of {}",
+                    format_size(max_file_size)
+                ));
+            }
+        }
+
+        let replaced = fs::metadata(zip_path).map_or(0, |metadata| metadata.len());
+        let used = self.used.saturating_sub(replaced) + size;
+        if let Some(max_total_size) = self.max_total_size {
+            if used > max_total_size {
+                return Err(format!(
+                    "Over the disk budget of {}",
+                    format_size(max_total_size)
+                ));
+            }
+        }
+        self.used = used;
+        Ok(Reservation { size, replaced })
+    }
+
+    // Gives back the reservation of a download which failed, the replaced zip is still there.
+    pub fn release(&mut self, reservation: Reservation) {
+        self.used = self.used.saturating_sub(reservation.size) + reservation.replaced;
+    }
+
+    // Counts a downloaded file which had no size in the database.
+    pub fn downloaded(&mut self, size: u64) {
+        self.used += size;
+    }
+
+    // Whether there's a total size to stay within.
+    pub fn is_limited(&self) -> bool {
+        self.max_total_size.is_some()
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+// Deletes all but the newest files of every mod in mods/<id>/, keeping files.yaml in sync.
+pub fn prune_old_versions(
+    paths: &PathsConfig,
+    keep_versions: usize,
+) -> Result<PruneReport, Box<dyn Error>> {
+    let mut report = PruneReport::default();
+    for gamebanana_id in mod_usage(paths)?.into_keys() {
+        let history_path = paths.mod_file_history(gamebanana_id);
+        if !history_path.is_file() {
+            continue;
+        }
+        let mut history = FileHistory::load(&history_path)?;
+        let mut files = history
+            .files
+            .iter()
+            .map(|(file_id, entry)| (entry.created_date, file_id.clone()))
+            .collect::<Vec<_>>();
+        files.sort_by(|a, b| b.cmp(a));
+
+        for (_, file_id) in files.into_iter().skip(keep_versions) {
+            let zip_path = paths.mod_file_zip(gamebanana_id, &file_id);
+            if let Ok(metadata) = fs::metadata(&zip_path) {
+                fs::remove_file(&zip_path)?;
+                report.files += 1;
+                report.bytes += metadata.len();
+            }
+            history.files.remove(&file_id);
+        }
+        history.save(&history_path)?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(max_total_size: u64, used: u64) -> Budget {
+        Budget {
+            max_total_size: Some(max_total_size),
+            max_file_size: None,
+            used,
+        }
+    }
+
+    fn no_zip() -> &'static Path {
+        Path::new("does/not/exist.zip")
+    }
+
+    #[test]
+    fn reserves_files_which_fit() {
+        let mut budget = budget(100, 50);
+        assert!(budget.reserve(Some(50), no_zip()).is_ok());
+        assert_eq!(budget.used(), 100);
+        assert!(budget.reserve(Some(1), no_zip()).is_err());
+        assert_eq!(budget.used(), 100);
+    }
+
+    #[test]
+    fn unknown_sizes_fit_while_the_budget_is_not_used_up() {
+        let mut budget = budget(100, 99);
+        assert_eq!(budget.reserve(None, no_zip()), Ok(Reservation::default()));
+        assert_eq!(budget.used(), 99);
+        assert!(self::budget(100, 100).reserve(None, no_zip()).is_err());
+    }
+
+    #[test]
+    fn release_gives_back_the_reservation() {
+        let mut budget = budget(100, 0);
+        let reservation = budget.reserve(Some(60), no_zip()).unwrap();
+        assert!(budget.reserve(Some(60), no_zip()).is_err());
+
+        budget.release(reservation);
+        assert_eq!(budget.used(), 0);
+        assert!(budget.reserve(Some(60), no_zip()).is_ok());
+    }
+
+    #[test]
+    fn replaced_zips_are_counted_again_when_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("1.zip");
+        fs::write(&zip_path, [0; 30]).unwrap();
+        let mut budget = budget(100, 30);
+
+        let reservation = budget.reserve(Some(80), &zip_path).unwrap();
+        assert_eq!(budget.used(), 80);
+        budget.release(reservation);
+        assert_eq!(budget.used(), 30);
+    }
+
+    #[test]
+    fn file_size_limit() {
+        let mut budget = Budget {
+            max_total_size: None,
+            max_file_size: Some(10),
+            used: 0,
+        };
+        assert!(budget.reserve(Some(11), no_zip()).is_err());
+        assert!(budget.reserve(Some(10), no_zip()).is_ok());
+        assert!(budget.reserve(None, no_zip()).is_ok());
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{self, File},
+    hash::Hasher,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use twox_hash::XxHash64;
+
+use super::{
+    cached_mods,
+    history::{self, FileHistory},
+    manifest::Manifest,
+    store::{downloaded_zips, StoreIndex},
+    FileDetails, ModDetail,
+};
+use crate::config::Config;
+
+pub fn xx_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+pub fn xx_hash_file(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hasher = XxHash64::with_seed(0);
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&buffer[..read]);
+    }
+    Ok(hasher.finish())
+}
+
+// The databases publish hashes as hex strings, which may drop leading zeros.
+// Files without published hashes can't be verified and are accepted.
+pub fn matches_any(expected: &[String], hash: u64) -> bool {
+    expected.is_empty()
+        || expected
+            .iter()
+            .any(|expected| u64::from_str_radix(expected.trim(), 16) == Ok(hash))
+}
+
+// Moves a zip out of the mods directory so it gets downloaded again, keeping it for inspection.
+pub fn quarantine(zip_path: &Path, quarantined_path: &Path) -> io::Result<()> {
+    if let Some(quarantine_dir) = quarantined_path.parent() {
+        fs::create_dir_all(quarantine_dir)?;
+    }
+    fs::rename(zip_path, quarantined_path)
+}
+
+#[derive(Debug, Default)]
+pub struct AuditReport {
+    pub verified: usize,
+    pub unverifiable: usize,
+    // GameBanana ID and the path the zip was moved to.
+    pub quarantined: Vec<(u64, PathBuf)>,
+}
+
+// The file a zip should hold. mods/<id>.zip holds the file in the manifest, a zip downloaded before
+// the manifest existed may hold any file of the mod. mods/<id>/<file id>.zip holds that file.
+fn expected_files<'a>(
+    mod_detail: &'a ModDetail,
+    manifest: &Manifest,
+    history_file_id: Option<&str>,
+) -> Vec<&'a FileDetails> {
+    let files = mod_detail.files.iter();
+    match (
+        history_file_id,
+        manifest.mods.get(&mod_detail.gamebanana_id),
+    ) {
+        (Some(file_id), _) => files
+            .filter(|file| history::file_id(file) == file_id)
+            .collect(),
+        (None, Some(entry)) => files.filter(|file| file.url == entry.url).collect(),
+        (None, None) => files.collect(),
+    }
+}
+
+// Re-verifies every zip in mods/, including the files in mods/<id>/, against the checksums of the
+// file it was downloaded from. Zips whose file has no checksums or left the database can't be verified.
+pub fn audit_mods(config: &Config) -> Result<AuditReport, Box<dyn Error>> {
+    let paths = &config.paths;
+    let mods_list = cached_mods(config)?;
+    let mods_by_id = mods_list
+        .iter()
+        .map(|mod_detail| (mod_detail.gamebanana_id, mod_detail))
+        .collect::<HashMap<_, _>>();
+    let manifest = Manifest::load(&paths.manifest())?;
+    let mut store_index = StoreIndex::load(&paths.store_index())?;
+
+    let mut report = AuditReport::default();
+    for zip in downloaded_zips(paths)? {
+        let history_file_id = zip
+            .file
+            .split_once('/')
+            .map(|(_, file)| file.trim_end_matches(".zip"));
+        let expected = mods_by_id
+            .get(&zip.gamebanana_id)
+            .map(|mod_detail| expected_files(mod_detail, &manifest, history_file_id))
+            .unwrap_or_default();
+        let hashes = expected
+            .iter()
+            .flat_map(|file| file.xx_hash.iter().cloned())
+            .collect::<Vec<_>>();
+        if hashes.is_empty() {
+            report.unverifiable += 1;
+            continue;
+        }
+
+        let hash = xx_hash_file(&zip.path)?;
+        if matches_any(&hashes, hash) {
+            report.verified += 1;
+            continue;
+        }
+
+        eprintln!(
+            "{} - Checksum mismatch - got {:016x}, expected {}",
+            zip.path.display(),
+            hash,
+            hashes.join(", ")
+        );
+        let quarantined_path = match history_file_id {
+            Some(file_id) => {
+                // Forgotten by the history so the next download with every file fetches it again.
+                let history_path = paths.mod_file_history(zip.gamebanana_id);
+                let mut history = FileHistory::load(&history_path)?;
+                if history.files.remove(file_id).is_some() {
+                    history.save(&history_path)?;
+                }
+                paths.quarantined_file_zip(zip.gamebanana_id, file_id)
+            }
+            None => paths.quarantined_zip(zip.gamebanana_id),
+        };
+        // The store holds the entries of the bad zip, which mustn't stand in for it once it's gone.
+        if store_index.remove(zip.gamebanana_id, &zip.file) {
+            store_index.save(&paths.store_index())?;
+        }
+        quarantine(&zip.path, &quarantined_path)?;
+        report
+            .quarantined
+            .push((zip.gamebanana_id, quarantined_path));
+    }
+
+    Ok(report)
+}
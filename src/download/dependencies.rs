@@ -0,0 +1,132 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    everest_update::{string_or_number, ModuleIndex},
+    store::ModFiles,
+};
+
+// Provided by the game and the mod loader, never downloaded.
+const BUILT_IN_MODULES: [&str; 3] = ["Celeste", "Everest", "EverestCore"];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EverestModule {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Version", deserialize_with = "string_or_number", default)]
+    pub version: String,
+    #[serde(rename = "Dependencies", default)]
+    pub dependencies: Vec<ModuleDependency>,
+    #[serde(rename = "OptionalDependencies", default)]
+    pub optional_dependencies: Vec<ModuleDependency>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModuleDependency {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Version", deserialize_with = "string_or_number", default)]
+    pub version: String,
+}
+
+// Modules declared in the everest.yaml (or everest.yml) at the root of a mod.
+pub fn read_everest_yaml(
+    mod_files: &ModFiles,
+    gamebanana_id: u64,
+) -> Result<Vec<EverestModule>, Box<dyn Error>> {
+    let everest_yaml = mod_files.everest_yaml(gamebanana_id)?;
+    let everest_yaml = everest_yaml.trim_start_matches('\u{feff}');
+    Ok(serde_yaml::from_str::<Option<Vec<EverestModule>>>(everest_yaml)?.unwrap_or_default())
+}
+
+// Written to the output directory as JSON.
+#[derive(Debug, Default, Serialize)]
+pub struct DependencyGraph {
+    pub mods: BTreeMap<u64, ModNode>,
+    // Dependencies which aren't in everest_update.yaml.
+    pub missing_modules: BTreeSet<String>,
+    // Resolved GameBanana IDs which aren't in the mod database.
+    pub missing_mods: BTreeSet<u64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ModNode {
+    pub modules: Vec<String>,
+    pub dependencies: Vec<ResolvedDependency>,
+    // Why the everest.yaml couldn't be read.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolvedDependency {
+    pub name: String,
+    pub version: String,
+    pub optional: bool,
+    pub gamebanana_id: Option<u64>,
+}
+
+impl DependencyGraph {
+    // Adds the mod and returns the GameBanana IDs of its required dependencies.
+    pub fn add_mod(
+        &mut self,
+        gamebanana_id: u64,
+        mod_files: &ModFiles,
+        module_index: &ModuleIndex,
+    ) -> Vec<u64> {
+        let mut node = ModNode::default();
+        let modules = match read_everest_yaml(mod_files, gamebanana_id) {
+            Ok(modules) => modules,
+            Err(err) => {
+                node.error = Some(err.to_string());
+                vec![]
+            }
+        };
+
+        for module in modules {
+            let dependencies = module
+                .dependencies
+                .iter()
+                .map(|dependency| (dependency, false))
+                .chain(
+                    module
+                        .optional_dependencies
+                        .iter()
+                        .map(|dependency| (dependency, true)),
+                );
+            for (dependency, optional) in dependencies {
+                if BUILT_IN_MODULES.contains(&dependency.name.as_str()) {
+                    continue;
+                }
+
+                let dependency_id = module_index.gamebanana_id(&dependency.name);
+                if dependency_id.is_none() && !optional {
+                    self.missing_modules.insert(dependency.name.clone());
+                }
+                node.dependencies.push(ResolvedDependency {
+                    name: dependency.name.clone(),
+                    version: dependency.version.clone(),
+                    optional,
+                    gamebanana_id: dependency_id,
+                });
+            }
+            node.modules.push(module.name);
+        }
+
+        let mut dependency_ids = node
+            .dependencies
+            .iter()
+            .filter(|dependency| !dependency.optional)
+            .filter_map(|dependency| dependency.gamebanana_id)
+            .filter(|dependency_id| *dependency_id != gamebanana_id)
+            .collect::<Vec<_>>();
+        dependency_ids.sort();
+        dependency_ids.dedup();
+
+        self.mods.insert(gamebanana_id, node);
+        dependency_ids
+    }
+}
@@ -0,0 +1,100 @@
+use std::{fmt, io, time::Duration};
+
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug)]
+pub enum DownloadError {
+    NoFiles,
+    Http {
+        status: StatusCode,
+        // From the Retry-After header, only the delay-seconds form is understood.
+        retry_after: Option<Duration>,
+    },
+    Timeout,
+    // Failed to connect, or the connection broke while reading the body.
+    Connection(String),
+    // The server couldn't resume the partial download.
+    RangeMismatch,
+    ChecksumMismatch {
+        got: u64,
+        expected: Vec<String>,
+    },
+    Io(io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Transient,
+    Permanent,
+}
+
+impl DownloadError {
+    // 4xx responses other than 429 won't change by asking again, neither will missing files or disk errors.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Http { status, .. }
+                if status.is_client_error() && *status != StatusCode::TOO_MANY_REQUESTS =>
+            {
+                ErrorKind::Permanent
+            }
+            Self::NoFiles | Self::Io(_) => ErrorKind::Permanent,
+            Self::Http { .. }
+            | Self::Timeout
+            | Self::Connection(_)
+            | Self::RangeMismatch
+            | Self::ChecksumMismatch { .. } => ErrorKind::Transient,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        if let Self::Http { retry_after, .. } = self {
+            *retry_after
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoFiles => write!(f, "No files available"),
+            Self::Http { status, .. } => write!(f, "HTTP {}", status),
+            Self::Timeout => write!(f, "Timed out"),
+            Self::Connection(err) => write!(f, "Connection error - {}", err),
+            Self::RangeMismatch => write!(f, "Partial download doesn't match the file"),
+            Self::ChecksumMismatch { got, expected } => write!(
+                f,
+                "Checksum mismatch - got {:016x}, expected {}",
+                got,
+                expected.join(", ")
+            ),
+            Self::Io(err) => write!(f, "IO error - {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout
+        } else if let Some(status) = err.status() {
+            Self::Http {
+                status,
+                retry_after: None,
+            }
+        } else {
+            Self::Connection(err.to_string())
+        }
+    }
+}
+
+impl From<io::Error> for DownloadError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
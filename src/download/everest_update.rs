@@ -0,0 +1,191 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::{select::UNCATEGORIZED, FileDetails, ModDetail};
+use crate::config::ModIndex;
+
+// Versions are usually strings, but YAML reads unquoted ones like 1.0 as numbers.
+pub(crate) fn string_or_number<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    Ok(match serde_yaml::Value::deserialize(deserializer)? {
+        serde_yaml::Value::String(string) => string,
+        serde_yaml::Value::Number(number) => number.to_string(),
+        serde_yaml::Value::Null => String::new(),
+        _ => return Err(serde::de::Error::custom("expected a string or a number")),
+    })
+}
+
+// Entry of everest_update.yaml, keyed by the Everest module name.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EverestUpdateEntry {
+    // Mod for mods, other types like Tool or Wip are listed too.
+    #[serde(rename = "GameBananaType")]
+    pub gamebanana_type: String,
+    #[serde(rename = "GameBananaId")]
+    pub gamebanana_id: u64,
+    #[serde(rename = "Version", deserialize_with = "string_or_number", default)]
+    pub version: String,
+    // The file Everest installs, with the same checksums as in the mod database.
+    #[serde(rename = "URL", default)]
+    pub url: Option<String>,
+    #[serde(rename = "MirrorURL", default)]
+    pub mirror_url: Option<String>,
+    #[serde(rename = "GameBananaFileId", default)]
+    pub gamebanana_file_id: Option<u64>,
+    #[serde(rename = "xxHash", default)]
+    pub xx_hash: Vec<String>,
+    // In bytes.
+    #[serde(rename = "Size", default)]
+    pub size: Option<u64>,
+    // Unix timestamp.
+    #[serde(rename = "LastUpdate", default)]
+    pub last_update: Option<u64>,
+    // Fields which aren't known yet.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_yaml::Value>,
+}
+
+impl EverestUpdateEntry {
+    fn is_mod(&self) -> bool {
+        self.gamebanana_type == "Mod"
+    }
+
+    fn file_details(&self) -> Option<FileDetails> {
+        Some(FileDetails {
+            url: self.url.clone()?,
+            created_date: self.last_update.unwrap_or_default(),
+            xx_hash: self.xx_hash.clone(),
+            size: self.size,
+            ..FileDetails::default()
+        })
+    }
+}
+
+// Everest module of a mod, a mod can ship several.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleVersion {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Version")]
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ModuleIndex {
+    modules: HashMap<String, EverestUpdateEntry>,
+}
+
+impl ModuleIndex {
+    pub fn from_everest_update(everest_update: &str) -> Result<ModuleIndex, Box<dyn Error>> {
+        Ok(ModuleIndex {
+            modules: serde_yaml::from_str(everest_update)?,
+        })
+    }
+
+    pub fn get(&self, module_name: &str) -> Option<&EverestUpdateEntry> {
+        self.modules.get(module_name)
+    }
+
+    pub fn gamebanana_id(&self, module_name: &str) -> Option<u64> {
+        Some(self.get(module_name)?.gamebanana_id)
+    }
+
+    // Modules of every GameBanana mod by GameBanana ID, sorted by module name.
+    pub fn mods(&self) -> BTreeMap<u64, Vec<(&str, &EverestUpdateEntry)>> {
+        let mut mods = BTreeMap::<u64, Vec<_>>::new();
+        for (name, entry) in &self.modules {
+            if entry.is_mod() {
+                mods.entry(entry.gamebanana_id)
+                    .or_default()
+                    .push((name.as_str(), entry));
+            }
+        }
+        for modules in mods.values_mut() {
+            modules.sort_by_key(|(name, _)| *name);
+        }
+        mods
+    }
+
+    // Fills in the Everest modules of every mod. Mods without an everest.yaml have none.
+    pub fn add_modules(&self, mods: &mut [ModDetail]) {
+        let modules = self.mods();
+        for mod_detail in mods {
+            mod_detail.everest_modules = modules
+                .get(&mod_detail.gamebanana_id)
+                .map_or_else(Vec::new, |modules| everest_modules(modules));
+        }
+    }
+
+    // The mods a download with the given index works with, see merge and add_modules.
+    pub fn apply(&self, mut database: Vec<ModDetail>, index: ModIndex) -> Vec<ModDetail> {
+        match index {
+            ModIndex::EverestUpdate => self.merge(&database),
+            ModIndex::ModSearchDatabase => {
+                self.add_modules(&mut database);
+                database
+            }
+        }
+    }
+
+    // The mods of everest_update.yaml merged with the mod database by GameBanana ID. Mods which
+    // aren't in the database get the module names as their name and select::UNCATEGORIZED. Files
+    // which aren't in the database are added from everest_update.yaml, missing sizes and checksums
+    // are filled in.
+    pub fn merge(&self, database: &[ModDetail]) -> Vec<ModDetail> {
+        let database = database
+            .iter()
+            .map(|mod_detail| (mod_detail.gamebanana_id, mod_detail))
+            .collect::<HashMap<_, _>>();
+
+        let mut mods = vec![];
+        for (gamebanana_id, modules) in self.mods() {
+            let mut mod_detail = match database.get(&gamebanana_id) {
+                Some(mod_detail) => (*mod_detail).clone(),
+                None => ModDetail {
+                    name: modules
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    gamebanana_id,
+                    category_name: UNCATEGORIZED.to_string(),
+                    gamebanana_type: Some("Mod".to_string()),
+                    ..ModDetail::default()
+                },
+            };
+            for file in modules.iter().filter_map(|(_, entry)| entry.file_details()) {
+                match mod_detail
+                    .files
+                    .iter_mut()
+                    .find(|known| known.url == file.url)
+                {
+                    Some(known) => {
+                        known.size = known.size.or(file.size);
+                        if known.xx_hash.is_empty() {
+                            known.xx_hash = file.xx_hash;
+                        }
+                    }
+                    None => mod_detail.files.push(file),
+                }
+            }
+            mod_detail.everest_modules = everest_modules(&modules);
+            mods.push(mod_detail);
+        }
+        mods
+    }
+}
+
+fn everest_modules(modules: &[(&str, &EverestUpdateEntry)]) -> Vec<ModuleVersion> {
+    modules
+        .iter()
+        .map(|(name, entry)| ModuleVersion {
+            name: name.to_string(),
+            version: entry.version.clone(),
+        })
+        .collect()
+}
@@ -0,0 +1,87 @@
+use std::{collections::BTreeMap, error::Error, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{checksum, write_atomic, FileDetails};
+use crate::config::PathsConfig;
+
+// GameBanana file ID from the last URL segment (https://gamebanana.com/mmdl/<file id>),
+// or a hash of the URL for other hosts.
+pub fn file_id(file: &FileDetails) -> String {
+    let last_segment = file.url.trim_end_matches('/').rsplit('/').next();
+    match last_segment {
+        Some(segment) if !segment.is_empty() && segment.chars().all(|ch| ch.is_ascii_digit()) => {
+            segment.to_string()
+        }
+        _ => format!("{:016x}", checksum::xx_hash(file.url.as_bytes())),
+    }
+}
+
+// Every downloaded file of a mod, stored as mods/<id>/files.yaml next to mods/<id>/<file id>.zip.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FileHistory {
+    pub files: BTreeMap<String, HistoryEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub url: String,
+    pub created_date: u64,
+    pub size: Option<u64>,
+    pub xx_hash: Vec<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(file: &FileDetails) -> HistoryEntry {
+        HistoryEntry {
+            url: file.url.clone(),
+            created_date: file.created_date,
+            size: file.size,
+            xx_hash: file.xx_hash.clone(),
+        }
+    }
+}
+
+impl FileHistory {
+    pub fn load(path: &Path) -> Result<FileHistory, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(FileHistory::default());
+        }
+        Ok(serde_yaml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        Ok(write_atomic(path, serde_yaml::to_string(self)?.as_bytes())?)
+    }
+
+    // Adds a downloaded file to the history file at path.
+    pub fn record(path: &Path, file: &FileDetails) -> Result<(), Box<dyn Error>> {
+        let mut history = FileHistory::load(path)?;
+        history.files.insert(file_id(file), HistoryEntry::new(file));
+        history.save(path)
+    }
+}
+
+// Gives mods/<id>/<file id>.zip the content of mods/<id>.zip, which has to hold the file, so the
+// latest file isn't downloaded twice. A hard link where the file system allows it, a copy otherwise.
+// Replacing mods/<id>.zip renames a new file over it, which leaves the link alone.
+// Returns false when there's no zip to link from.
+pub fn link_file(
+    paths: &PathsConfig,
+    gamebanana_id: u64,
+    file: &FileDetails,
+) -> Result<bool, Box<dyn Error>> {
+    let zip_path = paths.mod_zip(gamebanana_id);
+    if !zip_path.is_file() {
+        return Ok(false);
+    }
+    let file_zip = paths.mod_file_zip(gamebanana_id, &file_id(file));
+    if !file_zip.exists() {
+        fs::create_dir_all(paths.mod_history_dir(gamebanana_id))?;
+        if fs::hard_link(&zip_path, &file_zip).is_err() {
+            fs::copy(&zip_path, &file_zip)?;
+        }
+    }
+    FileHistory::record(&paths.mod_file_history(gamebanana_id), file)?;
+    Ok(true)
+}
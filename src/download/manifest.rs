@@ -0,0 +1,43 @@
+use std::{collections::BTreeMap, error::Error, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{write_atomic, FileDetails};
+
+// Which database file each downloaded zip came from, so refreshes can tell when a mod was updated.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub mods: BTreeMap<u64, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub url: String,
+    pub created_date: u64,
+}
+
+impl ManifestEntry {
+    pub fn new(file: &FileDetails) -> ManifestEntry {
+        ManifestEntry {
+            url: file.url.clone(),
+            created_date: file.created_date,
+        }
+    }
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+        Ok(serde_yaml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        Ok(write_atomic(path, serde_yaml::to_string(self)?.as_bytes())?)
+    }
+
+    pub fn is_current(&self, gamebanana_id: u64, file: &FileDetails) -> bool {
+        self.mods.get(&gamebanana_id) == Some(&ManifestEntry::new(file))
+    }
+}
@@ -0,0 +1,79 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use serde::Serialize;
+
+use super::{error::DownloadError, now};
+use crate::config::{DownloadConfig, MirrorRule};
+
+// Name used in the health report for the URL from the mod database.
+pub const ORIGINAL: &str = "original";
+
+impl MirrorRule {
+    pub fn rewrite(&self, url: &str) -> Option<String> {
+        let rest = url.strip_prefix(&self.prefix)?;
+        Some(format!("{}{}{}", self.replacement, rest, self.suffix))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Mirrors {
+    rules: Vec<MirrorRule>,
+    original_first: bool,
+    health: Mutex<BTreeMap<String, MirrorHealth>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MirrorHealth {
+    pub successes: u64,
+    pub failures: u64,
+    pub last_error: Option<String>,
+    // Unix timestamps.
+    pub last_success: Option<u64>,
+    pub last_failure: Option<u64>,
+}
+
+impl Mirrors {
+    pub fn new(download_config: &DownloadConfig) -> Mirrors {
+        Mirrors {
+            rules: download_config.mirrors.clone(),
+            original_first: download_config.original_first,
+            health: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Mirror name and URL in the order they should be tried. The original URL is always included.
+    pub fn candidates(&self, url: &str) -> Vec<(String, String)> {
+        let mut candidates = self
+            .rules
+            .iter()
+            .filter_map(|rule| Some((rule.name.clone(), rule.rewrite(url)?)))
+            .collect::<Vec<_>>();
+        let original = (ORIGINAL.to_string(), url.to_string());
+        if self.original_first {
+            candidates.insert(0, original);
+        } else {
+            candidates.push(original);
+        }
+        candidates
+    }
+
+    pub fn record(&self, mirror: &str, result: Result<(), &DownloadError>) {
+        let mut health = self.health.lock().unwrap();
+        let health = health.entry(mirror.to_string()).or_default();
+        match result {
+            Ok(()) => {
+                health.successes += 1;
+                health.last_success = Some(now());
+            }
+            Err(err) => {
+                health.failures += 1;
+                health.last_error = Some(err.to_string());
+                health.last_failure = Some(now());
+            }
+        }
+    }
+
+    pub fn health(&self) -> BTreeMap<String, MirrorHealth> {
+        self.health.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{
+    archive::read_zip_maps,
+    config::ZipLimits,
+    parse::parse,
+    statistics::{map_summary, MapSummary},
+};
+
+// One line of the statistics file, like stats --json with the mod added.
+#[derive(Debug, Serialize)]
+struct MapStatistics<'a> {
+    gamebanana_id: u64,
+    mod_name: &'a str,
+    name: &'a str,
+    statistics: Option<MapSummary>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct PipelineReport {
+    pub zips: usize,
+    pub maps: usize,
+    // Maps which didn't parse and zips which couldn't be read.
+    pub errors: usize,
+}
+
+// Parses the maps of every finished download on a pool of blocking workers while the other
+// downloads continue, appending the statistics of each map to a JSON lines file.
+pub struct Pipeline {
+    workers: Arc<Semaphore>,
+    tasks: JoinSet<Result<PipelineReport, io::Error>>,
+    output: Arc<Mutex<BufWriter<File>>>,
+    limits: ZipLimits,
+}
+
+impl Pipeline {
+    // Workers default to the number of CPUs.
+    pub fn new(path: &Path, workers: Option<usize>, limits: &ZipLimits) -> io::Result<Pipeline> {
+        let workers = workers.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|workers| workers.get())
+                .unwrap_or(1)
+        });
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Pipeline {
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+            tasks: JoinSet::new(),
+            output: Arc::new(Mutex::new(BufWriter::new(file))),
+            limits: limits.clone(),
+        })
+    }
+
+    pub fn submit(&mut self, gamebanana_id: u64, mod_name: String, zip_path: PathBuf) {
+        let workers = self.workers.clone();
+        let output = self.output.clone();
+        let limits = self.limits.clone();
+        self.tasks.spawn(async move {
+            let _permit = workers.acquire_owned().await.unwrap();
+            tokio::task::spawn_blocking(move || {
+                process_zip(gamebanana_id, &mod_name, &zip_path, &limits, &output)
+            })
+            .await
+            .map_err(io::Error::other)?
+        });
+    }
+
+    // Waits for the submitted zips to be processed.
+    pub async fn finish(mut self) -> Result<PipelineReport, Box<dyn Error>> {
+        let mut report = PipelineReport::default();
+        while let Some(result) = self.tasks.join_next().await {
+            let zip_report = result??;
+            report.zips += zip_report.zips;
+            report.maps += zip_report.maps;
+            report.errors += zip_report.errors;
+        }
+        self.output.lock().unwrap().flush()?;
+        Ok(report)
+    }
+}
+
+fn process_zip(
+    gamebanana_id: u64,
+    mod_name: &str,
+    zip_path: &Path,
+    limits: &ZipLimits,
+    output: &Mutex<BufWriter<File>>,
+) -> Result<PipelineReport, io::Error> {
+    let mut report = PipelineReport {
+        zips: 1,
+        ..PipelineReport::default()
+    };
+
+    let mut lines = vec![];
+    match read_zip_maps(zip_path, limits) {
+        Ok(map_files) => {
+            for map_file in map_files {
+                let statistics = parse(&map_file.bytes)
+                    .map_err(|err| err.to_string())
+                    .and_then(|map| map_summary(&map).ok_or_else(|| "Malformed rooms".to_string()));
+                report.maps += 1;
+                if statistics.is_err() {
+                    report.errors += 1;
+                }
+                lines.push(serde_json::to_string(&MapStatistics {
+                    gamebanana_id,
+                    mod_name,
+                    name: &map_file.name,
+                    statistics: statistics.as_ref().ok().cloned(),
+                    error: statistics.err(),
+                })?);
+            }
+        }
+        // Reported on a line without a map name, the other zips carry on.
+        Err(err) => {
+            report.errors += 1;
+            lines.push(serde_json::to_string(&MapStatistics {
+                gamebanana_id,
+                mod_name,
+                name: "",
+                statistics: None,
+                error: Some(err.to_string()),
+            })?);
+        }
+    }
+
+    // Written together so the lines of a mod stay next to each other.
+    let mut output = output.lock().unwrap();
+    for line in lines {
+        writeln!(output, "{}", line)?;
+    }
+    output.flush()?;
+    Ok(report)
+}
@@ -0,0 +1,234 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use super::format_size;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    Queued {
+        gamebanana_id: u64,
+        name: String,
+        url: Option<String>,
+        // From the mod database, None when it isn't known.
+        size: Option<u64>,
+    },
+    // Sent once the scheduler lets the download start, for every URL tried when there are mirrors.
+    Started {
+        gamebanana_id: u64,
+        attempt: u32,
+        url: String,
+    },
+    // Sent about every megabyte and when a transfer ends, bytes is the amount since the last event.
+    BytesReceived {
+        gamebanana_id: u64,
+        bytes: u64,
+    },
+    Retried {
+        gamebanana_id: u64,
+        attempt: u32,
+        error: String,
+        delay_secs: f64,
+    },
+    Finished {
+        gamebanana_id: u64,
+        name: String,
+        path: String,
+        attempts: u32,
+    },
+    Failed {
+        gamebanana_id: u64,
+        name: String,
+        error: String,
+        attempts: u32,
+    },
+}
+
+// Receives events from every download task, so implementations need their own synchronisation.
+pub trait ProgressReporter: Send + Sync {
+    fn report(&self, event: &ProgressEvent);
+}
+
+// Sends every event to each reporter.
+impl ProgressReporter for Vec<Box<dyn ProgressReporter>> {
+    fn report(&self, event: &ProgressEvent) {
+        for reporter in self {
+            reporter.report(event);
+        }
+    }
+}
+
+// Prints finished and failed downloads, plus a status line with the byte count and ETA.
+#[derive(Debug)]
+pub struct TerminalProgress {
+    state: Mutex<TerminalState>,
+}
+
+#[derive(Debug)]
+struct TerminalState {
+    started_at: Instant,
+    last_status: Instant,
+    queued: u64,
+    finished: u64,
+    failed: u64,
+    expected_bytes: u64,
+    received_bytes: u64,
+}
+
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+impl Default for TerminalProgress {
+    fn default() -> Self {
+        TerminalProgress {
+            state: Mutex::new(TerminalState {
+                started_at: Instant::now(),
+                last_status: Instant::now(),
+                queued: 0,
+                finished: 0,
+                failed: 0,
+                expected_bytes: 0,
+                received_bytes: 0,
+            }),
+        }
+    }
+}
+
+impl TerminalState {
+    fn status(&self) -> String {
+        let elapsed = self.started_at.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.received_bytes as f64 / elapsed
+        } else {
+            0.0
+        };
+        // Only an estimate, sizes from the database can be missing and retries download bytes again.
+        let eta = if rate > 0.0 && self.expected_bytes > self.received_bytes {
+            let seconds = ((self.expected_bytes - self.received_bytes) as f64 / rate) as u64;
+            format!(", ETA {}m{:02}s", seconds / 60, seconds % 60)
+        } else {
+            String::new()
+        };
+
+        format!(
+            "Progress: {}/{} files ({} failed), {} of {}, {}/s{}",
+            self.finished + self.failed,
+            self.queued,
+            self.failed,
+            format_size(self.received_bytes),
+            format_size(self.expected_bytes),
+            format_size(rate as u64),
+            eta
+        )
+    }
+}
+
+impl ProgressReporter for TerminalProgress {
+    fn report(&self, event: &ProgressEvent) {
+        let mut state = self.state.lock().unwrap();
+        match event {
+            ProgressEvent::Queued { size, .. } => {
+                state.queued += 1;
+                state.expected_bytes += size.unwrap_or_default();
+            }
+            ProgressEvent::Started { .. } => {}
+            ProgressEvent::BytesReceived { bytes, .. } => {
+                state.received_bytes += bytes;
+                if state.last_status.elapsed() >= STATUS_INTERVAL {
+                    state.last_status = Instant::now();
+                    println!("{}", state.status());
+                }
+            }
+            ProgressEvent::Retried {
+                gamebanana_id,
+                attempt,
+                error,
+                delay_secs,
+            } => eprintln!(
+                "Mod {} - Attempt {} failed - {} - retrying in {:.1}s",
+                gamebanana_id, attempt, error, delay_secs
+            ),
+            ProgressEvent::Finished {
+                gamebanana_id,
+                name,
+                path,
+                attempts,
+            } => {
+                state.finished += 1;
+                println!(
+                    "Downloaded {} of mod {}({}) - attempt {}",
+                    path, name, gamebanana_id, attempts
+                );
+            }
+            ProgressEvent::Failed {
+                gamebanana_id,
+                name,
+                error,
+                attempts,
+            } => {
+                state.failed += 1;
+                eprintln!(
+                    "Mod {}({}) - Download error after {} attempts - {}",
+                    name, gamebanana_id, attempts, error
+                );
+            }
+        }
+    }
+}
+
+// Appends one JSON object per event, with the unix time in milliseconds.
+#[derive(Debug)]
+pub struct JsonLinesProgress {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl JsonLinesProgress {
+    pub fn create(path: &Path) -> io::Result<JsonLinesProgress> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(JsonLinesProgress {
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct TimedEvent<'a> {
+    time: u128,
+    #[serde(flatten)]
+    event: &'a ProgressEvent,
+}
+
+impl ProgressReporter for JsonLinesProgress {
+    fn report(&self, event: &ProgressEvent) {
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        let mut writer = self.writer.lock().unwrap();
+        // A broken log shouldn't stop the downloads.
+        if let Ok(line) = serde_json::to_string(&TimedEvent { time, event }) {
+            let _ = writeln!(writer, "{}", line);
+        }
+        // Flushed at the end of each download so the log can be followed while it runs.
+        if matches!(
+            event,
+            ProgressEvent::Finished { .. } | ProgressEvent::Failed { .. }
+        ) {
+            let _ = writer.flush();
+        }
+    }
+}
+
+impl Drop for JsonLinesProgress {
+    fn drop(&mut self) {
+        if let Ok(writer) = self.writer.get_mut() {
+            let _ = writer.flush();
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use super::error::{DownloadError, ErrorKind};
+use crate::config::DownloadConfig;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    pub fn new(download_config: &DownloadConfig) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: download_config.retries.max(1),
+            base_delay: Duration::from_millis(download_config.retry_base_delay_ms),
+            max_delay: Duration::from_secs(download_config.retry_max_delay_secs),
+            jitter: true,
+        }
+    }
+
+    // Delay before the next attempt, None when the error is permanent or attempts are used up.
+    // attempt starts at 1 for the attempt which just failed.
+    pub fn next_delay(&self, attempt: u32, err: &DownloadError) -> Option<Duration> {
+        if err.kind() == ErrorKind::Permanent || attempt >= self.max_attempts {
+            return None;
+        }
+
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_delay);
+        // Full jitter spreads out retries of downloads which failed together.
+        let backoff = if self.jitter && !backoff.is_zero() {
+            rand::thread_rng().gen_range(Duration::ZERO..=backoff)
+        } else {
+            backoff
+        };
+
+        // The server's Retry-After is a lower bound, even above max_delay.
+        Some(match err.retry_after() {
+            Some(retry_after) => retry_after.max(backoff),
+            None => backoff,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::*;
+
+    fn policy(jitter: bool) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            jitter,
+        }
+    }
+
+    fn http(status: StatusCode, retry_after: Option<Duration>) -> DownloadError {
+        DownloadError::Http {
+            status,
+            retry_after,
+        }
+    }
+
+    fn server_error() -> DownloadError {
+        http(StatusCode::SERVICE_UNAVAILABLE, None)
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = policy(false);
+        let delays = (1..=6)
+            .map(|attempt| {
+                policy
+                    .next_delay(attempt, &server_error())
+                    .unwrap()
+                    .as_secs()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [1, 2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn jitter_stays_within_the_backoff() {
+        for attempt in 1..=6 {
+            let backoff = policy(false).next_delay(attempt, &server_error()).unwrap();
+            let delays = (0..100)
+                .map(|_| policy(true).next_delay(attempt, &server_error()).unwrap())
+                .collect::<Vec<_>>();
+            assert!(delays.iter().all(|delay| *delay <= backoff));
+            assert!(delays.iter().any(|delay| *delay < backoff));
+        }
+    }
+
+    #[test]
+    fn retry_after_is_a_lower_bound() {
+        let err = http(StatusCode::TOO_MANY_REQUESTS, Some(Duration::from_secs(30)));
+        // Even above max_delay, and with jitter.
+        assert_eq!(
+            policy(true).next_delay(1, &err),
+            Some(Duration::from_secs(30))
+        );
+
+        // A shorter Retry-After doesn't shorten the backoff.
+        let err = http(
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(Duration::from_millis(1)),
+        );
+        assert_eq!(
+            policy(false).next_delay(3, &err),
+            Some(Duration::from_secs(4))
+        );
+    }
+
+    #[test]
+    fn permanent_errors_and_the_last_attempt_are_not_retried() {
+        let policy = policy(false);
+        assert_eq!(
+            policy.next_delay(1, &http(StatusCode::NOT_FOUND, None)),
+            None
+        );
+        assert_eq!(policy.next_delay(10, &server_error()), None);
+        assert!(policy.next_delay(9, &server_error()).is_some());
+    }
+}
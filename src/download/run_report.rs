@@ -0,0 +1,165 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use super::{error::ErrorKind, now, write_atomic, DownloadFailure};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Skipped,
+    Downloaded,
+    Updated,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModReport {
+    pub name: String,
+    pub outcome: Outcome,
+    // Of the downloaded zip, older files of the mod aren't counted here.
+    pub bytes: Option<u64>,
+    // From the first attempt until the download finished or was given up.
+    pub duration_secs: Option<f64>,
+    pub error_kind: Option<ErrorKind>,
+    pub error: Option<String>,
+    pub skip_reason: Option<String>,
+}
+
+impl ModReport {
+    fn new(name: &str, outcome: Outcome) -> ModReport {
+        ModReport {
+            name: name.to_string(),
+            outcome,
+            bytes: None,
+            duration_secs: None,
+            error_kind: None,
+            error: None,
+            skip_reason: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Totals {
+    pub mods: usize,
+    pub skipped: usize,
+    pub downloaded: usize,
+    pub updated: usize,
+    pub failed: usize,
+    // Every downloaded file, including older files of the mods.
+    pub bytes: u64,
+    pub history_files: usize,
+}
+
+// What one download run did to each mod it looked at, written as JSON at the end of the run or
+// when it stops with an error.
+// Mods are keyed and sorted by GameBanana ID so the reports of two runs can be diffed.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    // Unix timestamps.
+    pub started: u64,
+    pub finished: u64,
+    pub duration_secs: f64,
+    // Why the run stopped early, None when it finished.
+    pub error: Option<String>,
+    pub totals: Totals,
+    pub mods: BTreeMap<u64, ModReport>,
+    #[serde(skip)]
+    start: Instant,
+    #[serde(skip)]
+    started_ms: u64,
+}
+
+impl Default for RunReport {
+    fn default() -> Self {
+        let started_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis() as u64);
+        RunReport {
+            started: started_ms / 1000,
+            finished: 0,
+            duration_secs: 0.0,
+            error: None,
+            totals: Totals::default(),
+            mods: BTreeMap::new(),
+            start: Instant::now(),
+            started_ms,
+        }
+    }
+}
+
+impl RunReport {
+    // Names the report, so runs which started in the same second are told apart.
+    pub fn started_ms(&self) -> u64 {
+        self.started_ms
+    }
+
+    pub fn skipped(&mut self, gamebanana_id: u64, name: &str, reason: &str) {
+        let mut report = ModReport::new(name, Outcome::Skipped);
+        report.skip_reason = Some(reason.to_string());
+        self.mods.insert(gamebanana_id, report);
+    }
+
+    pub fn downloaded(
+        &mut self,
+        gamebanana_id: u64,
+        name: &str,
+        outcome: Outcome,
+        bytes: u64,
+        duration: Duration,
+    ) {
+        let mut report = ModReport::new(name, outcome);
+        report.bytes = Some(bytes);
+        report.duration_secs = Some(duration.as_secs_f64());
+        self.mods.insert(gamebanana_id, report);
+        self.totals.bytes += bytes;
+    }
+
+    // Older or extra files of a mod only count towards the totals.
+    pub fn history_file(&mut self, bytes: u64) {
+        self.totals.history_files += 1;
+        self.totals.bytes += bytes;
+    }
+
+    pub fn failed(&mut self, failure: &DownloadFailure, duration: Duration) {
+        let mut report = ModReport::new(&failure.name, Outcome::Failed);
+        report.duration_secs = Some(duration.as_secs_f64());
+        report.error_kind = Some(failure.kind);
+        report.error = failure.attempts.last().cloned();
+        self.mods.insert(failure.gamebanana_id, report);
+    }
+
+    // Counts the outcomes and writes the report.
+    pub fn save(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        self.finished = now();
+        self.duration_secs = self.start.elapsed().as_secs_f64();
+        self.totals.mods = self.mods.len();
+        for (outcome, count) in [
+            (Outcome::Skipped, &mut self.totals.skipped),
+            (Outcome::Downloaded, &mut self.totals.downloaded),
+            (Outcome::Updated, &mut self.totals.updated),
+            (Outcome::Failed, &mut self.totals.failed),
+        ] {
+            *count = self
+                .mods
+                .values()
+                .filter(|report| report.outcome == outcome)
+                .count();
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(write_atomic(
+            path,
+            serde_json::to_string_pretty(self)?.as_bytes(),
+        )?)
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+use crate::config::DownloadConfig;
+
+// Shared by every download task. A task holds a host permit and a global permit while it downloads,
+// so a slow download only occupies its own slot instead of stalling a whole batch.
+#[derive(Debug)]
+pub struct Scheduler {
+    global: Arc<Semaphore>,
+    per_host_limit: Option<usize>,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+    bandwidth: Option<BandwidthLimiter>,
+}
+
+#[derive(Debug)]
+pub struct Permit {
+    _host: Option<OwnedSemaphorePermit>,
+    _global: OwnedSemaphorePermit,
+}
+
+impl Scheduler {
+    pub fn new(download_config: &DownloadConfig) -> Scheduler {
+        Scheduler {
+            global: Arc::new(Semaphore::new(download_config.concurrency.max(1))),
+            per_host_limit: download_config
+                .per_host_concurrency
+                .map(|limit| limit.max(1)),
+            hosts: Mutex::new(HashMap::new()),
+            bandwidth: download_config.bandwidth_limit.map(BandwidthLimiter::new),
+        }
+    }
+
+    // The host permit is taken first so tasks waiting on a busy host don't hold global slots.
+    pub async fn acquire(&self, url: &str) -> Permit {
+        let host = match self.per_host_limit {
+            Some(limit) => {
+                let host = reqwest::Url::parse(url)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_string))
+                    .unwrap_or_default();
+                let semaphore = self
+                    .hosts
+                    .lock()
+                    .unwrap()
+                    .entry(host)
+                    .or_insert_with(|| Arc::new(Semaphore::new(limit)))
+                    .clone();
+                Some(semaphore.acquire_owned().await.unwrap())
+            }
+            None => None,
+        };
+        let global = self.global.clone().acquire_owned().await.unwrap();
+
+        Permit {
+            _host: host,
+            _global: global,
+        }
+    }
+
+    // Waits until the bytes fit into the bandwidth cap, returns immediately without a cap.
+    pub async fn consume_bandwidth(&self, bytes: usize) {
+        if let Some(bandwidth) = &self.bandwidth {
+            bandwidth.consume(bytes as u64).await;
+        }
+    }
+}
+
+// Token bucket holding up to one second of bandwidth.
+#[derive(Debug)]
+struct BandwidthLimiter {
+    bytes_per_second: u64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl BandwidthLimiter {
+    fn new(bytes_per_second: u64) -> BandwidthLimiter {
+        let bytes_per_second = bytes_per_second.max(1);
+        BandwidthLimiter {
+            bytes_per_second,
+            state: Mutex::new((bytes_per_second as f64, Instant::now())),
+        }
+    }
+
+    async fn consume(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(state.1).as_secs_f64() * self.bytes_per_second as f64;
+            state.0 = (state.0 + refill).min(self.bytes_per_second as f64);
+            state.1 = now;
+
+            // Going negative reserves the bytes, later callers wait for the debt to be paid off.
+            state.0 -= bytes as f64;
+            if state.0 < 0.0 {
+                Duration::from_secs_f64(-state.0 / self.bytes_per_second as f64)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(concurrency: usize, per_host_concurrency: Option<usize>) -> Scheduler {
+        Scheduler::new(&DownloadConfig {
+            concurrency,
+            per_host_concurrency,
+            ..DownloadConfig::default()
+        })
+    }
+
+    // Time is paused in these tests, so sleeps finish at once and only the waits are measured.
+    async fn waited(consume: impl std::future::Future<Output = ()>) -> Duration {
+        let start = Instant::now();
+        consume.await;
+        start.elapsed()
+    }
+
+    fn assert_about(waited: Duration, expected_ms: u64) {
+        let expected = Duration::from_millis(expected_ms);
+        assert!(
+            waited >= expected && waited < expected + Duration::from_millis(5),
+            "waited {:?}, expected {:?}",
+            waited,
+            expected
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bucket_starts_with_a_second_of_bandwidth() {
+        let limiter = BandwidthLimiter::new(1000);
+        assert_about(waited(limiter.consume(1000)).await, 0);
+        assert_about(waited(limiter.consume(500)).await, 500);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bucket_refills_up_to_a_second() {
+        let limiter = BandwidthLimiter::new(1000);
+        limiter.consume(1000).await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_about(waited(limiter.consume(2000)).await, 1000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn later_callers_wait_for_the_debt() {
+        let limiter = BandwidthLimiter::new(1000);
+        assert_about(waited(limiter.consume(3000)).await, 2000);
+        // The first caller slept off its own debt, so the bucket is empty rather than negative.
+        assert_about(waited(limiter.consume(100)).await, 100);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_downloads_share_the_bandwidth() {
+        let limiter = Arc::new(BandwidthLimiter::new(1000));
+        let start = Instant::now();
+        let mut downloads = tokio::task::JoinSet::new();
+        for _ in 0..4 {
+            let limiter = limiter.clone();
+            downloads.spawn(async move {
+                for _ in 0..5 {
+                    limiter.consume(100).await;
+                }
+            });
+        }
+        while downloads.join_next().await.is_some() {}
+        // 2000 bytes, the first 1000 from the full bucket.
+        assert_about(start.elapsed(), 1000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn no_cap_without_a_bandwidth_limit() {
+        let scheduler = scheduler(1, None);
+        assert_about(waited(scheduler.consume_bandwidth(1 << 30)).await, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn per_host_limit() {
+        let scheduler = scheduler(10, Some(1));
+        let timeout = Duration::from_secs(1);
+        let first = scheduler.acquire("https://gamebanana.com/mmdl/1").await;
+
+        // Another host has its own limit.
+        let other_host =
+            tokio::time::timeout(timeout, scheduler.acquire("https://mirror.example/1"))
+                .await
+                .expect("another host shouldn't wait");
+        let same_host =
+            tokio::time::timeout(timeout, scheduler.acquire("https://gamebanana.com/mmdl/2")).await;
+        assert!(same_host.is_err());
+
+        drop(first);
+        tokio::time::timeout(timeout, scheduler.acquire("https://gamebanana.com/mmdl/2"))
+            .await
+            .expect("the host permit was released");
+        drop(other_host);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn global_limit_covers_every_host() {
+        let scheduler = scheduler(1, Some(5));
+        let timeout = Duration::from_secs(1);
+        let first = scheduler.acquire("https://gamebanana.com/mmdl/1").await;
+
+        let other_host =
+            tokio::time::timeout(timeout, scheduler.acquire("https://mirror.example/1")).await;
+        assert!(other_host.is_err());
+
+        drop(first);
+        tokio::time::timeout(timeout, scheduler.acquire("https://mirror.example/1"))
+            .await
+            .expect("the global permit was released");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waiting_for_a_host_holds_no_global_permit() {
+        let scheduler = Arc::new(scheduler(2, Some(1)));
+        let timeout = Duration::from_secs(1);
+        let _first = scheduler.acquire("https://gamebanana.com/mmdl/1").await;
+
+        let waiting = {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move {
+                let _permit = scheduler.acquire("https://gamebanana.com/mmdl/2").await;
+            })
+        };
+        tokio::task::yield_now().await;
+
+        // One global permit is left despite the task waiting on gamebanana.com.
+        tokio::time::timeout(timeout, scheduler.acquire("https://mirror.example/1"))
+            .await
+            .expect("the waiting task shouldn't take a global permit");
+        waiting.abort();
+    }
+}
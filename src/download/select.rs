@@ -0,0 +1,77 @@
+use regex::Regex;
+
+use super::ModDetail;
+use crate::config::DownloadConfig;
+
+// Category which matches every category in download.categories.
+pub const ANY_CATEGORY: &str = "*";
+// Category of mods which are only in everest_update.yaml, see everest_update::ModuleIndex::merge.
+pub const UNCATEGORIZED: &str = "Uncategorized";
+
+// Which mods of the database are downloaded. Explicit IDs are always selected, other mods need
+// an included category and have to pass the exclusions, name pattern and date range. Uncategorized
+// mods pass any category list, as their category isn't known, unless they're excluded.
+#[derive(Debug, Clone)]
+pub struct Selection {
+    categories: Vec<String>,
+    exclude_categories: Vec<String>,
+    ids: Vec<u64>,
+    name_regex: Option<Regex>,
+    created_after: Option<u64>,
+    created_before: Option<u64>,
+}
+
+impl Selection {
+    pub fn new(download_config: &DownloadConfig) -> Result<Selection, regex::Error> {
+        Ok(Selection {
+            categories: download_config.categories.clone(),
+            exclude_categories: download_config.exclude_categories.clone(),
+            ids: download_config.ids.clone(),
+            name_regex: download_config
+                .name_regex
+                .as_deref()
+                .map(Regex::new)
+                .transpose()?,
+            created_after: download_config.created_after,
+            created_before: download_config.created_before,
+        })
+    }
+
+    pub fn selects(&self, mod_detail: &ModDetail) -> bool {
+        if self.ids.contains(&mod_detail.gamebanana_id) {
+            return true;
+        }
+
+        let category_included = mod_detail.category_name == UNCATEGORIZED
+            || self
+                .categories
+                .iter()
+                .any(|category| category == ANY_CATEGORY || *category == mod_detail.category_name);
+        if !category_included || self.exclude_categories.contains(&mod_detail.category_name) {
+            return false;
+        }
+
+        if let Some(name_regex) = &self.name_regex {
+            if !name_regex.is_match(&mod_detail.name) {
+                return false;
+            }
+        }
+
+        if self.created_after.is_some() || self.created_before.is_some() {
+            let Some(latest_file) = mod_detail.latest_file() else {
+                return false;
+            };
+            if self
+                .created_after
+                .is_some_and(|created_after| latest_file.created_date < created_after)
+                || self
+                    .created_before
+                    .is_some_and(|created_before| latest_file.created_date >= created_before)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    io::SeekFrom,
+    path::PathBuf,
+    pin::Pin,
+    sync::Mutex,
+    time::Duration,
+};
+
+use reqwest::{
+    header::{CONTENT_RANGE, RANGE, RETRY_AFTER},
+    StatusCode,
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::{error::DownloadError, ModDetail};
+use crate::config::DownloadConfig;
+
+pub type SourceFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// Where the mod database, everest_update.yaml and mod files come from. The downloader only talks
+// to a source, so it can run against a local directory or the in-memory MemorySource.
+pub trait ModSource: Send + Sync {
+    // The mod database as YAML, cached to paths.mods_list.
+    fn mod_database(&self) -> SourceFuture<'_, Result<String, DownloadError>>;
+
+    fn everest_update(&self) -> SourceFuture<'_, Result<String, DownloadError>>;
+
+    // Starts reading the file, from resume_from when the source supports it.
+    fn fetch_file<'a>(
+        &'a self,
+        url: &'a str,
+        resume_from: u64,
+    ) -> SourceFuture<'a, Result<FetchedFile, DownloadError>>;
+}
+
+pub struct FetchedFile {
+    // Whether the body continues at resume_from, otherwise it starts at the beginning of the file.
+    pub resumed: bool,
+    pub body: Box<dyn FileBody>,
+}
+
+pub trait FileBody: Send {
+    // The next chunk of the file, None at the end.
+    fn chunk(&mut self) -> SourceFuture<'_, Result<Option<Vec<u8>>, DownloadError>>;
+}
+
+// The mod database and files over HTTP, with ranges for resuming.
+#[derive(Debug)]
+pub struct HttpSource {
+    client: reqwest::Client,
+    database_url: String,
+    everest_update_url: String,
+}
+
+impl HttpSource {
+    pub fn new(download_config: &DownloadConfig) -> Result<HttpSource, reqwest::Error> {
+        Ok(HttpSource {
+            client: reqwest::ClientBuilder::new()
+                .timeout(Duration::from_secs(download_config.timeout_secs))
+                .build()?,
+            database_url: download_config.database_url.clone(),
+            everest_update_url: download_config.everest_update_url.clone(),
+        })
+    }
+
+    async fn fetch_text(&self, url: &str) -> Result<String, DownloadError> {
+        Ok(self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?)
+    }
+}
+
+impl ModSource for HttpSource {
+    fn mod_database(&self) -> SourceFuture<'_, Result<String, DownloadError>> {
+        Box::pin(self.fetch_text(&self.database_url))
+    }
+
+    fn everest_update(&self) -> SourceFuture<'_, Result<String, DownloadError>> {
+        Box::pin(self.fetch_text(&self.everest_update_url))
+    }
+
+    fn fetch_file<'a>(
+        &'a self,
+        url: &'a str,
+        resume_from: u64,
+    ) -> SourceFuture<'a, Result<FetchedFile, DownloadError>> {
+        Box::pin(async move {
+            let mut request = self.client.get(url);
+            if resume_from > 0 {
+                request = request.header(RANGE, format!("bytes={}-", resume_from));
+            }
+            let response = request.send().await?;
+
+            if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+                return Err(DownloadError::RangeMismatch);
+            }
+            if !response.status().is_success() {
+                let retry_after = response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse::<u64>().ok())
+                    .map(Duration::from_secs);
+                return Err(DownloadError::Http {
+                    status: response.status(),
+                    retry_after,
+                });
+            }
+
+            // Servers without range support answer with the whole file.
+            let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+            if resumed && range_start(&response) != Some(resume_from) {
+                return Err(DownloadError::RangeMismatch);
+            }
+
+            Ok(FetchedFile {
+                resumed,
+                body: Box::new(response),
+            })
+        })
+    }
+}
+
+// First byte of a partial response, from a Content-Range such as "bytes 100-199/200".
+fn range_start(response: &reqwest::Response) -> Option<u64> {
+    let content_range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (start, _) = content_range.strip_prefix("bytes ")?.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+impl FileBody for reqwest::Response {
+    fn chunk(&mut self) -> SourceFuture<'_, Result<Option<Vec<u8>>, DownloadError>> {
+        Box::pin(async move {
+            Ok(reqwest::Response::chunk(self)
+                .await?
+                .map(|chunk| chunk.to_vec()))
+        })
+    }
+}
+
+// A directory holding mod_search_database.yaml, everest_update.yaml and the mod files, each file
+// named after the last segment of its URL, e.g. https://gamebanana.com/mmdl/123456 is 123456.
+#[derive(Debug)]
+pub struct LocalSource {
+    dir: PathBuf,
+}
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+impl LocalSource {
+    pub fn new(dir: PathBuf) -> LocalSource {
+        LocalSource { dir }
+    }
+
+    pub fn file_path(&self, url: &str) -> PathBuf {
+        let name = url
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        self.dir.join(name)
+    }
+}
+
+impl ModSource for LocalSource {
+    fn mod_database(&self) -> SourceFuture<'_, Result<String, DownloadError>> {
+        Box::pin(async move {
+            Ok(tokio::fs::read_to_string(self.dir.join("mod_search_database.yaml")).await?)
+        })
+    }
+
+    fn everest_update(&self) -> SourceFuture<'_, Result<String, DownloadError>> {
+        Box::pin(async move {
+            Ok(tokio::fs::read_to_string(self.dir.join("everest_update.yaml")).await?)
+        })
+    }
+
+    fn fetch_file<'a>(
+        &'a self,
+        url: &'a str,
+        resume_from: u64,
+    ) -> SourceFuture<'a, Result<FetchedFile, DownloadError>> {
+        Box::pin(async move {
+            let mut file = tokio::fs::File::open(self.file_path(url)).await?;
+            if resume_from > file.metadata().await?.len() {
+                return Err(DownloadError::RangeMismatch);
+            }
+            file.seek(SeekFrom::Start(resume_from)).await?;
+            Ok(FetchedFile {
+                resumed: true,
+                body: Box::new(file),
+            })
+        })
+    }
+}
+
+impl FileBody for tokio::fs::File {
+    fn chunk(&mut self) -> SourceFuture<'_, Result<Option<Vec<u8>>, DownloadError>> {
+        Box::pin(async move {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let length = self.read(&mut chunk).await?;
+            chunk.truncate(length);
+            Ok((length > 0).then_some(chunk))
+        })
+    }
+}
+
+// Serves everything from memory. Failures can be queued per URL to exercise the retry logic,
+// and every fetch is counted.
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    mods: Vec<ModDetail>,
+    everest_update: String,
+    files: HashMap<String, Vec<u8>>,
+    failures: Mutex<HashMap<String, VecDeque<DownloadError>>>,
+    fetches: Mutex<HashMap<String, usize>>,
+}
+
+impl MemorySource {
+    pub fn new(mods: Vec<ModDetail>) -> MemorySource {
+        MemorySource {
+            mods,
+            ..MemorySource::default()
+        }
+    }
+
+    pub fn with_everest_update(mut self, everest_update: String) -> MemorySource {
+        self.everest_update = everest_update;
+        self
+    }
+
+    pub fn with_file(mut self, url: &str, bytes: Vec<u8>) -> MemorySource {
+        self.files.insert(url.to_string(), bytes);
+        self
+    }
+
+    // The next fetch of the URL fails with the error, before any file is served.
+    pub fn fail_next(&self, url: &str, err: DownloadError) {
+        self.failures
+            .lock()
+            .unwrap()
+            .entry(url.to_string())
+            .or_default()
+            .push_back(err);
+    }
+
+    pub fn fetches(&self, url: &str) -> usize {
+        self.fetches
+            .lock()
+            .unwrap()
+            .get(url)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl ModSource for MemorySource {
+    fn mod_database(&self) -> SourceFuture<'_, Result<String, DownloadError>> {
+        Box::pin(async move {
+            serde_yaml::to_string(&self.mods)
+                .map_err(|err| DownloadError::Io(std::io::Error::other(err)))
+        })
+    }
+
+    fn everest_update(&self) -> SourceFuture<'_, Result<String, DownloadError>> {
+        Box::pin(async move { Ok(self.everest_update.clone()) })
+    }
+
+    fn fetch_file<'a>(
+        &'a self,
+        url: &'a str,
+        resume_from: u64,
+    ) -> SourceFuture<'a, Result<FetchedFile, DownloadError>> {
+        Box::pin(async move {
+            *self
+                .fetches
+                .lock()
+                .unwrap()
+                .entry(url.to_string())
+                .or_default() += 1;
+            let failure = self
+                .failures
+                .lock()
+                .unwrap()
+                .get_mut(url)
+                .and_then(VecDeque::pop_front);
+            if let Some(err) = failure {
+                return Err(err);
+            }
+
+            let Some(bytes) = self.files.get(url) else {
+                return Err(DownloadError::Http {
+                    status: StatusCode::NOT_FOUND,
+                    retry_after: None,
+                });
+            };
+            let Some(rest) = bytes.get(resume_from as usize..) else {
+                return Err(DownloadError::RangeMismatch);
+            };
+            Ok(FetchedFile {
+                resumed: true,
+                body: Box::new(Some(rest.to_vec())),
+            })
+        })
+    }
+}
+
+// The whole file as a single chunk.
+impl FileBody for Option<Vec<u8>> {
+    fn chunk(&mut self) -> SourceFuture<'_, Result<Option<Vec<u8>>, DownloadError>> {
+        Box::pin(async move { Ok(self.take()) })
+    }
+}
@@ -0,0 +1,154 @@
+use std::{collections::BTreeMap, error::Error, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{error::ErrorKind, now, write_atomic, DownloadFailure, FileDetails};
+
+// Status of every mod the downloader has dealt with, kept between runs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DownloadState {
+    pub mods: BTreeMap<u64, ModState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Downloaded,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModState {
+    pub name: String,
+    pub status: Status,
+    pub url: Option<String>,
+    // Of the downloaded zip.
+    pub size: Option<u64>,
+    pub xx_hash: Option<String>,
+    // Attempts in the last run which tried the mod.
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub last_error_kind: Option<ErrorKind>,
+    // Unix timestamps.
+    pub last_attempt: Option<u64>,
+    pub last_success: Option<u64>,
+    pub skip_reason: Option<String>,
+}
+
+impl ModState {
+    fn new(name: &str) -> ModState {
+        ModState {
+            name: name.to_string(),
+            status: Status::Skipped,
+            url: None,
+            size: None,
+            xx_hash: None,
+            attempts: 0,
+            last_error: None,
+            last_error_kind: None,
+            last_attempt: None,
+            last_success: None,
+            skip_reason: None,
+        }
+    }
+}
+
+impl DownloadState {
+    pub fn load(path: &Path) -> Result<DownloadState, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(DownloadState::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        Ok(write_atomic(
+            path,
+            serde_json::to_string_pretty(self)?.as_bytes(),
+        )?)
+    }
+
+    fn entry(&mut self, gamebanana_id: u64, name: &str) -> &mut ModState {
+        let state = self
+            .mods
+            .entry(gamebanana_id)
+            .or_insert_with(|| ModState::new(name));
+        // Mods which left the database are only known by ID, keep their old name.
+        if !name.is_empty() {
+            state.name = name.to_string();
+        }
+        state
+    }
+
+    pub fn record_success(
+        &mut self,
+        gamebanana_id: u64,
+        name: &str,
+        file: &FileDetails,
+        size: u64,
+        xx_hash: u64,
+        attempts: u32,
+    ) {
+        let time = now();
+        let state = self.entry(gamebanana_id, name);
+        state.status = Status::Downloaded;
+        state.url = Some(file.url.clone());
+        state.size = Some(size);
+        state.xx_hash = Some(format!("{:016x}", xx_hash));
+        state.attempts = attempts;
+        state.last_attempt = Some(time);
+        state.last_success = Some(time);
+        state.skip_reason = None;
+    }
+
+    // A mod whose zip was there before the state was kept, only what's known about the zip is recorded.
+    pub fn record_downloaded_before(
+        &mut self,
+        gamebanana_id: u64,
+        name: &str,
+        url: Option<&str>,
+        size: Option<u64>,
+        xx_hash: Option<u64>,
+    ) {
+        let state = self.entry(gamebanana_id, name);
+        state.status = Status::Downloaded;
+        state.url = url.map(str::to_string);
+        state.size = size;
+        state.xx_hash = xx_hash.map(|xx_hash| format!("{:016x}", xx_hash));
+    }
+
+    // The last error and success are kept, so a failed update still shows the previous download.
+    pub fn record_failure(&mut self, failure: &DownloadFailure) {
+        let state = self.entry(failure.gamebanana_id, &failure.name);
+        state.status = Status::Failed;
+        if failure.url.is_some() {
+            state.url = failure.url.clone();
+        }
+        state.attempts = failure.attempts.len() as u32;
+        state.last_error = failure.attempts.last().cloned();
+        state.last_error_kind = Some(failure.kind);
+        state.last_attempt = Some(now());
+        state.skip_reason = None;
+    }
+
+    // Keeps the status of mods which were downloaded or tried before, only new mods are Skipped.
+    pub fn record_skip(&mut self, gamebanana_id: u64, name: &str, reason: &str) {
+        self.entry(gamebanana_id, name).skip_reason = Some(reason.to_string());
+    }
+
+    pub fn failed_ids(&self) -> Vec<u64> {
+        self.mods
+            .iter()
+            .filter(|(_, state)| state.status == Status::Failed)
+            .map(|(gamebanana_id, _)| *gamebanana_id)
+            .collect()
+    }
+
+    pub fn count(&self, status: Status) -> usize {
+        self.mods
+            .values()
+            .filter(|state| state.status == status)
+            .count()
+    }
+}
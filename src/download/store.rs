@@ -0,0 +1,340 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{checksum::xx_hash, write_atomic};
+use crate::{
+    archive::{read_zip_maps, MapFile, SafeZip},
+    config::{Config, PathsConfig, ZipLimits},
+};
+
+// Which entries of every extracted zip went into the store, keyed by GameBanana ID and then the
+// zip path relative to mods_dir, e.g. 123.zip or 123/456789.zip when keeping every file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StoreIndex {
+    pub mods: BTreeMap<u64, BTreeMap<String, ExtractedZip>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtractedZip {
+    // Of the zip when it was extracted, a replaced zip is extracted again.
+    pub size: u64,
+    pub modified: u64,
+    // Entry path inside the zip to the xxHash of its content, as hex.
+    pub entries: BTreeMap<String, String>,
+}
+
+impl StoreIndex {
+    pub fn load(path: &Path) -> Result<StoreIndex, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(StoreIndex::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        Ok(write_atomic(
+            path,
+            serde_json::to_string_pretty(self)?.as_bytes(),
+        )?)
+    }
+
+    // Whether the zip was extracted, it may have been pruned since.
+    pub fn contains(&self, gamebanana_id: u64, file: &str) -> bool {
+        self.extracted(gamebanana_id, file).is_some()
+    }
+
+    pub fn extracted(&self, gamebanana_id: u64, file: &str) -> Option<&ExtractedZip> {
+        self.mods.get(&gamebanana_id)?.get(file)
+    }
+
+    // Whether the zip was extracted and deleted since, so the store is all that's left of it.
+    pub fn is_pruned(&self, paths: &PathsConfig, gamebanana_id: u64, file: &str) -> bool {
+        self.contains(gamebanana_id, file) && !paths.mods_dir.join(file).exists()
+    }
+
+    // Forgets an extracted zip, returns whether it was in the index.
+    pub fn remove(&mut self, gamebanana_id: u64, file: &str) -> bool {
+        let Some(files) = self.mods.get_mut(&gamebanana_id) else {
+            return false;
+        };
+        let removed = files.remove(file).is_some();
+        if files.is_empty() {
+            self.mods.remove(&gamebanana_id);
+        }
+        removed
+    }
+
+    fn is_current(&self, gamebanana_id: u64, file: &str, size: u64, modified: u64) -> bool {
+        self.mods
+            .get(&gamebanana_id)
+            .and_then(|files| files.get(file))
+            .is_some_and(|zip| zip.size == size && zip.modified == modified)
+    }
+}
+
+// Maps, their metadata, everest.yaml and dialog files, everything else in a zip is left out.
+fn is_stored(entry: &str) -> bool {
+    let entry = entry.to_ascii_lowercase();
+    entry.ends_with(".bin")
+        || entry.ends_with(".meta.yaml")
+        || is_everest_yaml(&entry)
+        || (entry.starts_with("dialog/") && entry.ends_with(".txt"))
+}
+
+fn is_everest_yaml(entry: &str) -> bool {
+    entry.eq_ignore_ascii_case("everest.yaml") || entry.eq_ignore_ascii_case("everest.yml")
+}
+
+// Reads a mod's maps and everest.yaml from mods/<id>.zip, or from the store once extract --prune
+// deleted the zip.
+#[derive(Debug)]
+pub struct ModFiles<'a> {
+    paths: &'a PathsConfig,
+    limits: &'a ZipLimits,
+    index: StoreIndex,
+}
+
+impl<'a> ModFiles<'a> {
+    pub fn new(
+        paths: &'a PathsConfig,
+        limits: &'a ZipLimits,
+    ) -> Result<ModFiles<'a>, Box<dyn Error>> {
+        Ok(ModFiles {
+            paths,
+            limits,
+            index: StoreIndex::load(&paths.store_index())?,
+        })
+    }
+
+    // Entries of mods/<id>.zip in the store, None while the zip is there.
+    fn pruned_entries(&self, gamebanana_id: u64) -> Option<&BTreeMap<String, String>> {
+        if self.paths.mod_zip(gamebanana_id).is_file() {
+            return None;
+        }
+        let zip = self
+            .index
+            .extracted(gamebanana_id, &format!("{}.zip", gamebanana_id))?;
+        Some(&zip.entries)
+    }
+
+    fn read_object(&self, hash: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let object_path = self.paths.store_object(hash);
+        fs::read(&object_path).map_err(|err| format!("{}: {}", object_path.display(), err).into())
+    }
+
+    pub fn is_downloaded(&self, gamebanana_id: u64) -> bool {
+        self.paths.mod_zip(gamebanana_id).is_file() || self.pruned_entries(gamebanana_id).is_some()
+    }
+
+    // Every .bin in the mod, None when it isn't downloaded.
+    pub fn maps(&self, gamebanana_id: u64) -> Result<Option<Vec<MapFile>>, Box<dyn Error>> {
+        let Some(entries) = self.pruned_entries(gamebanana_id) else {
+            let zip_path = self.paths.mod_zip(gamebanana_id);
+            if !zip_path.is_file() {
+                return Ok(None);
+            }
+            return Ok(Some(read_zip_maps(&zip_path, self.limits)?));
+        };
+
+        let mut maps = vec![];
+        for (name, hash) in entries {
+            if Path::new(name).extension().is_some_and(|ext| ext == "bin") {
+                maps.push(MapFile {
+                    name: name.clone(),
+                    bytes: self.read_object(hash)?,
+                });
+            }
+        }
+        Ok(Some(maps))
+    }
+
+    // Content of the everest.yaml (or everest.yml) at the root of the mod.
+    pub fn everest_yaml(&self, gamebanana_id: u64) -> Result<String, Box<dyn Error>> {
+        let bytes = match self.pruned_entries(gamebanana_id) {
+            Some(entries) => {
+                let (_, hash) = entries
+                    .iter()
+                    .find(|(name, _)| is_everest_yaml(name))
+                    .ok_or("No everest.yaml")?;
+                self.read_object(hash)?
+            }
+            None => {
+                let mut zip = SafeZip::open(&self.paths.mod_zip(gamebanana_id), self.limits)?;
+                let index = zip.find(is_everest_yaml).ok_or("No everest.yaml")?;
+                zip.read(index)?
+            }
+        };
+        Ok(String::from_utf8(bytes)?)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct DownloadedZip {
+    pub gamebanana_id: u64,
+    // Relative to mods_dir, the key in StoreIndex.
+    pub file: String,
+    pub path: PathBuf,
+}
+
+// Every mods/<id>.zip and mods/<id>/<file id>.zip.
+pub(crate) fn downloaded_zips(paths: &PathsConfig) -> Result<Vec<DownloadedZip>, Box<dyn Error>> {
+    let is_zip = |path: &Path| path.is_file() && path.extension().is_some_and(|ext| ext == "zip");
+    let parse_id = |name: Option<&std::ffi::OsStr>| name?.to_str()?.parse::<u64>().ok();
+
+    let mut zips = vec![];
+    for entry in fs::read_dir(&paths.mods_dir)? {
+        let path = entry?.path();
+        if is_zip(&path) {
+            if let Some(gamebanana_id) = parse_id(path.file_stem()) {
+                zips.push(DownloadedZip {
+                    gamebanana_id,
+                    file: format!("{}.zip", gamebanana_id),
+                    path,
+                });
+            }
+        } else if let Some(gamebanana_id) = parse_id(path.file_name()) {
+            // Per-mod history directories, the quarantine isn't numeric.
+            for entry in fs::read_dir(&path)? {
+                let path = entry?.path();
+                if is_zip(&path) {
+                    zips.push(DownloadedZip {
+                        gamebanana_id,
+                        file: format!(
+                            "{}/{}",
+                            gamebanana_id,
+                            path.file_name().unwrap().to_string_lossy()
+                        ),
+                        path,
+                    });
+                }
+            }
+        }
+    }
+    zips.sort();
+    Ok(zips)
+}
+
+// Writes the entries into the store, returns the index entries and how many objects were new.
+fn extract_zip(
+    paths: &PathsConfig,
+    limits: &ZipLimits,
+    zip_path: &Path,
+) -> Result<(BTreeMap<String, String>, usize), Box<dyn Error>> {
+    let mut zip = SafeZip::open(zip_path, limits)?;
+
+    let mut entries = BTreeMap::new();
+    let mut new_objects = 0;
+    for i in 0..zip.entries().len() {
+        let entry = &zip.entries()[i];
+        if !entry.is_file || !is_stored(&entry.name) {
+            continue;
+        }
+        let name = entry.name.clone();
+
+        let bytes = zip.read(i)?;
+        let hash = format!("{:016x}", xx_hash(&bytes));
+        let object_path = paths.store_object(&hash);
+        if !object_path.exists() {
+            fs::create_dir_all(object_path.parent().unwrap())?;
+            write_atomic(&object_path, &bytes)?;
+            new_objects += 1;
+        }
+        entries.insert(name, hash);
+    }
+
+    Ok((entries, new_objects))
+}
+
+#[derive(Debug, Default)]
+pub struct ExtractReport {
+    pub extracted: usize,
+    // Already in the index and unchanged.
+    pub unchanged: usize,
+    pub entries: usize,
+    pub new_objects: usize,
+    pub pruned: usize,
+    pub pruned_bytes: u64,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+// Extracts every downloaded zip into the store, deleting the zips afterwards when prune is set.
+// Pruned mods still count as downloaded, see StoreIndex::mods. Zips are only deleted once the
+// index listing them is saved, so an interrupted run can't lose track of a pruned zip.
+pub fn extract_mods(config: &Config, prune: bool) -> Result<ExtractReport, Box<dyn Error>> {
+    let paths = &config.paths;
+    let mut index = StoreIndex::load(&paths.store_index())?;
+    fs::create_dir_all(&paths.store_dir)?;
+
+    let mut report = ExtractReport::default();
+    // Zips which are in the index, with their size.
+    let mut prunable = vec![];
+    for DownloadedZip {
+        gamebanana_id,
+        file,
+        path: zip_path,
+    } in downloaded_zips(paths)?
+    {
+        let metadata = fs::metadata(&zip_path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        if index.is_current(gamebanana_id, &file, metadata.len(), modified) {
+            report.unchanged += 1;
+        } else {
+            match extract_zip(paths, &config.zip, &zip_path) {
+                Ok((entries, new_objects)) => {
+                    report.extracted += 1;
+                    report.entries += entries.len();
+                    report.new_objects += new_objects;
+                    index.mods.entry(gamebanana_id).or_default().insert(
+                        file,
+                        ExtractedZip {
+                            size: metadata.len(),
+                            modified,
+                            entries,
+                        },
+                    );
+                }
+                Err(err) => {
+                    eprintln!("{}: {}", zip_path.display(), err);
+                    report.failed.push((zip_path, err.to_string()));
+                    continue;
+                }
+            }
+        }
+
+        if prune {
+            prunable.push((zip_path, metadata.len()));
+        }
+    }
+
+    index.save(&paths.store_index())?;
+    for (zip_path, size) in prunable {
+        fs::remove_file(&zip_path)?;
+        report.pruned += 1;
+        report.pruned_bytes += size;
+    }
+    Ok(report)
+}
+
+// Number of distinct objects the index refers to, and how many entries point at them.
+pub fn dedupe_stats(index: &StoreIndex) -> (usize, usize) {
+    let hashes = index
+        .mods
+        .values()
+        .flat_map(|files| files.values())
+        .flat_map(|zip| zip.entries.values())
+        .collect::<Vec<_>>();
+    let objects = hashes.iter().collect::<BTreeSet<_>>().len();
+    (objects, hashes.len())
+}
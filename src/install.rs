@@ -0,0 +1,162 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive::{read_zip_maps, MapFile},
+    config::ZipLimits,
+};
+
+// Name used for the maps shipped with the game.
+pub const VANILLA: &str = "Celeste";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModKind {
+    Vanilla,
+    Zip,
+    // An unzipped mod, usually one which is being worked on.
+    Directory,
+}
+
+// Maps of the game or of one installed mod. Map names are relative to the mod root like in a zip,
+// e.g. Maps/author/campaign/map.bin.
+#[derive(Debug, Clone)]
+pub struct InstalledMod {
+    pub name: String,
+    pub kind: ModKind,
+    pub path: PathBuf,
+    pub maps: Vec<MapFile>,
+}
+
+// The Content directory is inside the app bundle on macOS.
+fn content_dir(celeste_dir: &Path) -> PathBuf {
+    let bundled = celeste_dir
+        .join("Contents")
+        .join("Resources")
+        .join("Content");
+    if bundled.is_dir() {
+        bundled
+    } else {
+        celeste_dir.join("Content")
+    }
+}
+
+// Every .bin under dir, named relative to root.
+fn read_dir_maps(root: &Path, dir: &Path, maps: &mut Vec<MapFile>) -> Result<(), Box<dyn Error>> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            read_dir_maps(root, &entry, maps)?;
+        } else if entry
+            .extension()
+            .is_some_and(|extension| extension == "bin")
+        {
+            let name = entry
+                .strip_prefix(root)?
+                .to_str()
+                .ok_or("Not a string")?
+                .replace('\\', "/");
+            maps.push(MapFile {
+                name,
+                bytes: fs::read(&entry)?,
+            });
+        }
+    }
+    Ok(())
+}
+
+// Vanilla maps from Content/Maps, then every zip and unzipped mod in Mods. Mods which can't be
+// read are skipped and added to rejected, mods without maps are left out.
+pub fn scan_install(
+    celeste_dir: &Path,
+    limits: &ZipLimits,
+    rejected: &mut Vec<(PathBuf, Box<dyn Error>)>,
+) -> Result<Vec<InstalledMod>, Box<dyn Error>> {
+    let content_dir = content_dir(celeste_dir);
+    let mods_dir = celeste_dir.join("Mods");
+    if !content_dir.join("Maps").is_dir() && !mods_dir.is_dir() {
+        return Err(format!(
+            "{} doesn't look like a Celeste install, it has neither Content/Maps nor Mods",
+            celeste_dir.display()
+        )
+        .into());
+    }
+
+    let mut installed_mods = vec![];
+    if content_dir.join("Maps").is_dir() {
+        let mut maps = vec![];
+        read_dir_maps(&content_dir, &content_dir.join("Maps"), &mut maps)?;
+        installed_mods.push(InstalledMod {
+            name: VANILLA.to_string(),
+            kind: ModKind::Vanilla,
+            path: content_dir,
+            maps,
+        });
+    }
+
+    if mods_dir.is_dir() {
+        let mut entries = fs::read_dir(&mods_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+
+        for path in entries {
+            let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let name = name.to_string();
+            let (kind, maps) = if path.is_dir() {
+                let mut maps = vec![];
+                let maps_dir = path.join("Maps");
+                let result = if maps_dir.is_dir() {
+                    read_dir_maps(&path, &maps_dir, &mut maps)
+                } else {
+                    Ok(())
+                };
+                (ModKind::Directory, result.map(|()| maps))
+            } else if path.extension().is_some_and(|extension| extension == "zip") {
+                (
+                    ModKind::Zip,
+                    read_zip_maps(&path, limits).map_err(|err| err.into()),
+                )
+            } else {
+                continue;
+            };
+
+            match maps {
+                Ok(maps) if maps.is_empty() => {}
+                Ok(maps) => installed_mods.push(InstalledMod {
+                    name,
+                    kind,
+                    path,
+                    maps,
+                }),
+                Err(err) => rejected.push((path, err)),
+            }
+        }
+    }
+
+    Ok(installed_mods)
+}
+
+// Every installed map, named <mod>/<map path> like maps from zips in a directory.
+pub fn read_install_maps(
+    celeste_dir: &Path,
+    limits: &ZipLimits,
+    rejected: &mut Vec<(PathBuf, Box<dyn Error>)>,
+) -> Result<Vec<MapFile>, Box<dyn Error>> {
+    let mut maps = vec![];
+    for installed_mod in scan_install(celeste_dir, limits, rejected)? {
+        for mut map in installed_mod.maps {
+            map.name = format!("{}/{}", installed_mod.name, map.name);
+            maps.push(map);
+        }
+    }
+    Ok(maps)
+}
@@ -0,0 +1,13 @@
+pub use celeste_maps_core::{entity, parse, ParseError};
+
+pub mod archive;
+pub mod config;
+
+#[cfg(feature = "download")]
+pub mod download;
+pub mod install;
+pub mod statistics;
+#[cfg(feature = "upload")]
+pub mod upload_stats;
+#[cfg(feature = "viewer")]
+pub mod viewer;
@@ -1,7 +1,599 @@
-mod download;
-mod parse;
-mod statistics;
-mod upload_stats;
-mod viewer;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-fn main() {}
+use celeste_maps_data::{
+    archive::{read_map_files, MapFile},
+    config::{Config, ZipLimits},
+    install::read_install_maps,
+    parse::{parse, Element},
+    statistics::map_summary,
+};
+use clap::{Parser, Subcommand};
+
+#[cfg(feature = "download")]
+use celeste_maps_data::{config::ModIndex, download::DownloadOptions};
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Download, inspect and collect statistics for Celeste maps"
+)]
+struct Cli {
+    /// Config file, defaults to celeste-maps-data.toml if it exists
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Overrides paths.mods_dir
+    #[arg(long, global = true)]
+    mods_dir: Option<PathBuf>,
+    /// Overrides paths.mods_list
+    #[arg(long, global = true)]
+    mods_list: Option<PathBuf>,
+    /// Overrides paths.output_dir
+    #[arg(long, global = true)]
+    output_dir: Option<PathBuf>,
+    /// Overrides paths.celeste_dir
+    #[arg(long, global = true)]
+    celeste_dir: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[cfg(feature = "download")]
+#[derive(Debug, clap::Args)]
+struct DownloadArgs {
+    /// Overrides download.database_url
+    #[arg(long)]
+    database_url: Option<String>,
+    /// Overrides download.index with everest_update, selecting from the mods with an everest.yaml
+    #[arg(long)]
+    everest_update_index: bool,
+    /// Overrides download.source_dir, a directory with mod_search_database.yaml and the mod files
+    #[arg(long)]
+    source_dir: Option<PathBuf>,
+    /// Overrides download.concurrency
+    #[arg(long)]
+    concurrency: Option<usize>,
+    /// Overrides download.per_host_concurrency
+    #[arg(long)]
+    per_host_concurrency: Option<usize>,
+    /// Overrides download.bandwidth_limit, in bytes per second
+    #[arg(long)]
+    bandwidth_limit: Option<u64>,
+    /// Overrides download.timeout_secs
+    #[arg(long)]
+    timeout_secs: Option<u64>,
+    /// Overrides download.retries
+    #[arg(long)]
+    retries: Option<u32>,
+    /// Overrides download.categories, can be repeated, * selects every category
+    #[arg(long = "category")]
+    categories: Vec<String>,
+    /// Overrides download.exclude_categories, can be repeated
+    #[arg(long = "exclude-category")]
+    exclude_categories: Vec<String>,
+    /// Overrides download.ids, GameBanana IDs which are always selected, can be repeated
+    #[arg(long = "id")]
+    ids: Vec<u64>,
+    /// Overrides download.name_regex
+    #[arg(long)]
+    name_regex: Option<String>,
+    /// Overrides download.created_after, a unix timestamp
+    #[arg(long)]
+    created_after: Option<u64>,
+    /// Overrides download.created_before, a unix timestamp
+    #[arg(long)]
+    created_before: Option<u64>,
+    /// List what would be downloaded and the estimated size without downloading
+    #[arg(long)]
+    dry_run: bool,
+    /// Also download the mods listed as dependencies in everest.yaml
+    #[arg(long)]
+    dependencies: bool,
+    /// Overrides download.all_files, keeping every file of a mod in mods/<id>/<file id>.zip
+    #[arg(long)]
+    all_files: bool,
+    /// Append download progress events to this file as JSON lines
+    #[arg(long)]
+    event_log: Option<PathBuf>,
+    /// Only download the mods which failed in earlier runs
+    #[arg(long)]
+    retry_failed: bool,
+    /// Parse each zip as soon as it's downloaded, appending map statistics to the output directory
+    #[arg(long)]
+    process: bool,
+    /// Overrides download.process_workers
+    #[arg(long)]
+    process_workers: Option<usize>,
+    /// Overrides paths.run_report, where the JSON report of the run is written
+    #[arg(long)]
+    run_report: Option<PathBuf>,
+    /// Overrides budget.max_total_size_mb
+    #[arg(long)]
+    max_total_size_mb: Option<u64>,
+    /// Overrides budget.max_file_size_mb
+    #[arg(long)]
+    max_file_size_mb: Option<u64>,
+    /// Overrides budget.keep_versions
+    #[arg(long)]
+    keep_versions: Option<usize>,
+}
+
+#[cfg(feature = "download")]
+impl DownloadArgs {
+    // Applies the overrides to the config and returns the remaining options.
+    fn apply(self, config: &mut Config, refresh: bool) -> DownloadOptions {
+        let download = &mut config.download;
+        if let Some(database_url) = self.database_url {
+            download.database_url = database_url;
+        }
+        if self.everest_update_index {
+            download.index = ModIndex::EverestUpdate;
+        }
+        if self.source_dir.is_some() {
+            download.source_dir = self.source_dir;
+        }
+        if let Some(concurrency) = self.concurrency {
+            download.concurrency = concurrency;
+        }
+        if self.per_host_concurrency.is_some() {
+            download.per_host_concurrency = self.per_host_concurrency;
+        }
+        if self.bandwidth_limit.is_some() {
+            download.bandwidth_limit = self.bandwidth_limit;
+        }
+        if let Some(timeout_secs) = self.timeout_secs {
+            download.timeout_secs = timeout_secs;
+        }
+        if let Some(retries) = self.retries {
+            download.retries = retries;
+        }
+        if !self.categories.is_empty() {
+            download.categories = self.categories;
+        }
+        if !self.exclude_categories.is_empty() {
+            download.exclude_categories = self.exclude_categories;
+        }
+        if !self.ids.is_empty() {
+            download.ids = self.ids;
+        }
+        if self.name_regex.is_some() {
+            download.name_regex = self.name_regex;
+        }
+        if self.created_after.is_some() {
+            download.created_after = self.created_after;
+        }
+        if self.created_before.is_some() {
+            download.created_before = self.created_before;
+        }
+        if self.all_files {
+            download.all_files = true;
+        }
+        if self.process_workers.is_some() {
+            download.process_workers = self.process_workers;
+        }
+        if self.run_report.is_some() {
+            config.paths.run_report = self.run_report;
+        }
+        let budget = &mut config.budget;
+        if self.max_total_size_mb.is_some() {
+            budget.max_total_size_mb = self.max_total_size_mb;
+        }
+        if self.max_file_size_mb.is_some() {
+            budget.max_file_size_mb = self.max_file_size_mb;
+        }
+        if self.keep_versions.is_some() {
+            budget.keep_versions = self.keep_versions;
+        }
+
+        DownloadOptions {
+            refresh,
+            dry_run: self.dry_run,
+            dependencies: self.dependencies,
+            event_log: self.event_log,
+            retry_failed: self.retry_failed,
+            process: self.process,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Download every map mod from the mod database into mods/
+    #[cfg(feature = "download")]
+    Download(DownloadArgs),
+    /// Re-fetch the mod database and download only new or updated mods
+    #[cfg(feature = "download")]
+    Refresh(DownloadArgs),
+    /// Re-verify downloaded zips against the mod database checksums, quarantining mismatches
+    #[cfg(feature = "download")]
+    Audit,
+    /// Extract maps, metadata, everest.yaml and dialog files from the downloaded zips into the store
+    #[cfg(feature = "download")]
+    Extract {
+        /// Delete each zip once it's extracted, the mod still counts as downloaded
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Summarise the download state, listing the mods which failed
+    #[cfg(feature = "download")]
+    Status,
+    /// List the mods using the most disk and their share of it, largest first
+    #[cfg(feature = "download")]
+    Usage {
+        /// Number of mods to list
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+    },
+    /// Upload statistics of the downloaded maps to the database in LIBSQL_URL
+    #[cfg(feature = "upload")]
+    Upload {
+        /// Overrides upload.database_url
+        #[arg(long)]
+        database_url: Option<String>,
+    },
+    /// Open a map in the viewer
+    #[cfg(feature = "viewer")]
+    View {
+        /// A .bin map or a mod .zip
+        path: PathBuf,
+        /// Map to open when the zip has more than one, e.g. 1-ForsakenCity
+        #[arg(long)]
+        map: Option<String>,
+    },
+    /// Print statistics for every map in a .bin, .zip or directory
+    Stats {
+        path: PathBuf,
+        /// Print one JSON object per line
+        #[arg(long)]
+        json: bool,
+        /// Read the path as a Celeste install, with the maps of Content/Maps and Mods
+        #[arg(long)]
+        install: bool,
+    },
+    /// Print the element tree of a .bin map
+    Dump { path: PathBuf },
+    /// Check that every map in a .bin, .zip or directory parses
+    Validate {
+        path: PathBuf,
+        /// Read the path as a Celeste install, with the maps of Content/Maps and Mods
+        #[arg(long)]
+        install: bool,
+    },
+}
+
+fn dump_element(element: &Element, depth: usize) {
+    print!("{}{}", "  ".repeat(depth), element.name);
+    for (name, value) in &element.attributes {
+        print!(" {}={}", name, value);
+    }
+    println!();
+
+    for child in &element.children {
+        dump_element(child, depth + 1);
+    }
+}
+
+#[cfg(feature = "viewer")]
+fn select_map(maps: Vec<MapFile>, name: Option<&str>) -> Result<MapFile, Box<dyn Error>> {
+    let names = maps
+        .iter()
+        .map(|map| map.name.clone())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let map = match name {
+        Some(name) => maps
+            .into_iter()
+            .find(|map| map.name == name || map.short_name() == name),
+        None if maps.len() == 1 => maps.into_iter().next(),
+        None => return Err(format!("Pick a map with --map, available maps:\n{}", names).into()),
+    };
+    map.ok_or_else(|| format!("Map not found, available maps:\n{}", names).into())
+}
+
+// The maps and how many zips or mods were rejected, which are reported here.
+fn read_maps(
+    path: &Path,
+    install: bool,
+    limits: &ZipLimits,
+) -> Result<(Vec<MapFile>, usize), Box<dyn Error>> {
+    let mut rejected = vec![];
+    let maps = if install {
+        read_install_maps(path, limits, &mut rejected)?
+    } else {
+        read_map_files(path, limits, &mut rejected)?
+    };
+    for (path, err) in &rejected {
+        eprintln!("{}: {}", path.display(), err);
+    }
+    Ok((maps, rejected.len()))
+}
+
+fn stats((maps, rejected): (Vec<MapFile>, usize), json: bool) -> Result<ExitCode, Box<dyn Error>> {
+    let mut exit_code = if rejected == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    };
+    for map_file in maps {
+        let summary = parse(&map_file.bytes)
+            .map_err(|err| err.to_string())
+            .and_then(|map| map_summary(&map).ok_or_else(|| "Malformed rooms".to_string()));
+
+        match summary {
+            Ok(summary) if json => {
+                println!(
+                    "{}",
+                    serde_json::json!({ "name": map_file.name, "statistics": summary })
+                );
+            }
+            Ok(summary) => println!(
+                "{}: {} rooms, {} entities, {}x{}",
+                map_file.name, summary.rooms, summary.entities, summary.width, summary.height
+            ),
+            Err(err) => {
+                eprintln!("{}: {}", map_file.name, err);
+                exit_code = ExitCode::FAILURE;
+            }
+        }
+    }
+    Ok(exit_code)
+}
+
+fn validate((maps, rejected): (Vec<MapFile>, usize)) -> ExitCode {
+    let number_of_maps = maps.len();
+    let mut number_of_invalid_maps = 0;
+    for map_file in maps {
+        if let Err(err) = parse(&map_file.bytes) {
+            println!("{}: {}", map_file.name, err);
+            number_of_invalid_maps += 1;
+        }
+    }
+
+    println!(
+        "{} out of {} maps are valid.",
+        number_of_maps - number_of_invalid_maps,
+        number_of_maps
+    );
+    if rejected > 0 {
+        println!("{} zips were rejected.", rejected);
+    }
+    if number_of_invalid_maps == 0 && rejected == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+// Scripts can tell from the exit code whether any mod failed to download.
+#[cfg(feature = "download")]
+fn download_exit_code(failures: usize) -> ExitCode {
+    if failures == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+#[cfg(feature = "download")]
+fn download_status(config: &Config) -> Result<(), Box<dyn Error>> {
+    use celeste_maps_data::download::state::{DownloadState, Status};
+
+    let path = config.paths.download_state();
+    if !path.exists() {
+        println!(
+            "No download state in {}, nothing was downloaded yet.",
+            path.display()
+        );
+        return Ok(());
+    }
+    let state = DownloadState::load(&path)?;
+
+    println!(
+        "{} mods: {} downloaded, {} failed, {} skipped.",
+        state.mods.len(),
+        state.count(Status::Downloaded),
+        state.count(Status::Failed),
+        state.count(Status::Skipped)
+    );
+
+    let mut skip_reasons = std::collections::BTreeMap::<&str, usize>::new();
+    for mod_state in state.mods.values() {
+        if let Some(reason) = &mod_state.skip_reason {
+            *skip_reasons.entry(reason).or_default() += 1;
+        }
+    }
+    for (reason, count) in skip_reasons {
+        println!("Skipped - {}: {}", reason, count);
+    }
+
+    for gamebanana_id in state.failed_ids() {
+        let mod_state = &state.mods[&gamebanana_id];
+        println!(
+            "Failed {}({}) - {} attempts - {}",
+            mod_state.name,
+            gamebanana_id,
+            mod_state.attempts,
+            mod_state.last_error.as_deref().unwrap_or("unknown error")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(feature = "download")]
+fn disk_usage(config: &Config, top: usize) -> Result<(), Box<dyn Error>> {
+    use celeste_maps_data::download::{
+        budget::{disk_usage, mod_usage},
+        format_size,
+        state::DownloadState,
+    };
+
+    let state = DownloadState::load(&config.paths.download_state())?;
+    let total = disk_usage(&config.paths)?;
+    let mut mods = mod_usage(&config.paths)?.into_iter().collect::<Vec<_>>();
+    mods.sort_by(|(id_a, size_a), (id_b, size_b)| size_b.cmp(size_a).then(id_a.cmp(id_b)));
+
+    let share = |size: u64| 100.0 * size as f64 / total.max(1) as f64;
+    for &(gamebanana_id, size) in mods.iter().take(top) {
+        let name = state
+            .mods
+            .get(&gamebanana_id)
+            .map_or("", |mod_state| mod_state.name.as_str());
+        println!(
+            "{:>10} {:>5.1}%  {}({})",
+            format_size(size),
+            share(size),
+            name,
+            gamebanana_id
+        );
+    }
+
+    let mods_size = mods.iter().map(|(_, size)| size).sum::<u64>();
+    println!(
+        "{} mods use {} ({:.1}%), {} in total with the store and caches.",
+        mods.len(),
+        format_size(mods_size),
+        share(mods_size),
+        format_size(total)
+    );
+    if let Some(max_total_size_mb) = config.budget.max_total_size_mb {
+        let max_total_size = max_total_size_mb * 1024 * 1024;
+        println!(
+            "Budget: {} of {} used ({:.1}%).",
+            format_size(total),
+            format_size(max_total_size),
+            100.0 * total as f64 / max_total_size.max(1) as f64
+        );
+    }
+    Ok(())
+}
+
+fn load_config(cli: &Cli) -> Result<Config, Box<dyn Error>> {
+    let mut config = Config::load(cli.config.as_deref())?;
+    if let Some(mods_dir) = &cli.mods_dir {
+        config.paths.mods_dir = mods_dir.clone();
+    }
+    if let Some(mods_list) = &cli.mods_list {
+        config.paths.mods_list = Some(mods_list.clone());
+    }
+    if let Some(output_dir) = &cli.output_dir {
+        config.paths.output_dir = output_dir.clone();
+    }
+    if let Some(celeste_dir) = &cli.celeste_dir {
+        config.paths.celeste_dir = Some(celeste_dir.clone());
+    }
+    Ok(config)
+}
+
+fn run(cli: Cli) -> Result<ExitCode, Box<dyn Error>> {
+    #[allow(unused_mut, unused_variables)]
+    let mut config = load_config(&cli)?;
+
+    match cli.command {
+        #[cfg(feature = "download")]
+        Command::Download(args) => {
+            let options = args.apply(&mut config, false);
+            return Ok(download_exit_code(
+                celeste_maps_data::download::download_maps(&config, &options)?,
+            ));
+        }
+        #[cfg(feature = "download")]
+        Command::Refresh(args) => {
+            let options = args.apply(&mut config, true);
+            return Ok(download_exit_code(
+                celeste_maps_data::download::download_maps(&config, &options)?,
+            ));
+        }
+        #[cfg(feature = "download")]
+        Command::Audit => {
+            let report = celeste_maps_data::download::checksum::audit_mods(&config)?;
+            for (gamebanana_id, path) in &report.quarantined {
+                println!("Quarantined mod {} to {}", gamebanana_id, path.display());
+            }
+            println!(
+                "{} verified, {} without checksums, {} quarantined.",
+                report.verified,
+                report.unverifiable,
+                report.quarantined.len()
+            );
+            if !report.quarantined.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        #[cfg(feature = "download")]
+        Command::Extract { prune } => {
+            use celeste_maps_data::download::{format_size, store};
+
+            let report = store::extract_mods(&config, prune)?;
+            let index = store::StoreIndex::load(&config.paths.store_index())?;
+            let (objects, entries) = store::dedupe_stats(&index);
+            println!(
+                "{} zips extracted ({} entries, {} new objects), {} unchanged, {} failed.",
+                report.extracted,
+                report.entries,
+                report.new_objects,
+                report.unchanged,
+                report.failed.len()
+            );
+            println!(
+                "Store: {} entries in {} distinct objects.",
+                entries, objects
+            );
+            if prune {
+                println!(
+                    "{} zips pruned, {} freed.",
+                    report.pruned,
+                    format_size(report.pruned_bytes)
+                );
+            }
+            if !report.failed.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        #[cfg(feature = "download")]
+        Command::Status => download_status(&config)?,
+        #[cfg(feature = "download")]
+        Command::Usage { top } => disk_usage(&config, top)?,
+        #[cfg(feature = "upload")]
+        Command::Upload { database_url } => {
+            if database_url.is_some() {
+                config.upload.database_url = database_url;
+            }
+            celeste_maps_data::upload_stats::upload_stats(&config)?
+        }
+        #[cfg(feature = "viewer")]
+        Command::View { path, map } => {
+            let (maps, _) = read_maps(&path, false, &config.zip)?;
+            let map_file = select_map(maps, map.as_deref())?;
+            let map = parse(&map_file.bytes)?;
+            celeste_maps_data::viewer::view_map(&map);
+        }
+        Command::Stats {
+            path,
+            json,
+            install,
+        } => return stats(read_maps(&path, install, &config.zip)?, json),
+        Command::Dump { path } => {
+            let map = parse(&std::fs::read(path)?)?;
+            println!("package {}", map.package_name);
+            dump_element(&map.root, 0);
+        }
+        Command::Validate { path, install } => {
+            return Ok(validate(read_maps(&path, install, &config.zip)?))
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
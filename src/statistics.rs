@@ -1,6 +1,6 @@
 use serde::Serialize;
 
-use crate::parse::Map;
+use crate::{entity::entities, parse::Map};
 
 #[derive(Debug, Clone, Copy)]
 pub struct BoundingBox {
@@ -19,6 +19,14 @@ pub struct RoomDetail {
     pub tiles: String,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct MapSummary {
+    pub rooms: usize,
+    pub entities: usize,
+    pub width: i64,
+    pub height: i64,
+}
+
 pub fn bounding_box(map: &Map) -> Option<BoundingBox> {
     let rooms = map.root.get_child("levels")?;
 
@@ -78,3 +86,15 @@ pub fn room_details(map: &Map) -> Option<Vec<RoomDetail>> {
 
     Some(room_details)
 }
+
+pub fn map_summary(map: &Map) -> Option<MapSummary> {
+    let rooms = map.root.get_child("levels")?;
+    let bounds = bounding_box(map)?;
+
+    Some(MapSummary {
+        rooms: rooms.children.len(),
+        entities: rooms.children.iter().map(|room| entities(room).len()).sum(),
+        width: bounds.width,
+        height: bounds.height,
+    })
+}
@@ -1,81 +1,256 @@
-use std::{
-    env,
-    ffi::OsStr,
-    fs::{self, File},
-    io::Read,
-};
+use std::{collections::HashSet, env, error::Error, fs};
 
 use dotenv::dotenv;
-use libsql::Builder;
+use libsql::{Builder, Connection};
+use serde::Serialize;
 use tokio::runtime::Runtime;
 
 use crate::{
-    download::ModDetail,
+    archive::MapFile,
+    config::Config,
+    download::{cached_mods, store::ModFiles, ModDetail},
+    install::scan_install,
     parse::parse,
-    statistics::{bounding_box, room_details},
+    statistics::{bounding_box, room_details, BoundingBox, RoomDetail},
 };
 
-pub fn upload_stats() {
+// Zips which failed the safety checks, written to the output directory.
+#[derive(Debug, Serialize)]
+struct ZipViolation {
+    gamebanana_id: u64,
+    name: String,
+    error: String,
+}
+
+struct MapRow {
+    name: String,
+    // None for vanilla maps and local mods.
+    gamebanana_id: Option<u64>,
+    room_details: Vec<RoomDetail>,
+    bounding_box: BoundingBox,
+}
+
+// Parses the maps of one mod into rows named <mod name> / <map>.bin, skipping maps which don't parse.
+fn map_rows(
+    mod_name: &str,
+    gamebanana_id: Option<u64>,
+    map_files: Vec<MapFile>,
+    rows: &mut Vec<MapRow>,
+) {
+    for map_file in map_files {
+        if let Ok(map) = parse(&map_file.bytes) {
+            let (Some(room_details), Some(bounding_box)) = (room_details(&map), bounding_box(&map))
+            else {
+                eprintln!(
+                    "Mod {} - Map {} has malformed rooms",
+                    mod_name, map_file.name
+                );
+                continue;
+            };
+            rows.push(MapRow {
+                name: format!("{} / {}.bin", mod_name, map_file.short_name()),
+                gamebanana_id,
+                room_details,
+                bounding_box,
+            });
+        }
+    }
+}
+
+// Databases created before mod details were uploaded only have the maps table without gamebanana_id,
+// and the mods table didn't have the Everest modules at first.
+async fn migrate(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS mods(
+            gamebanana_id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            category TEXT NOT NULL,
+            author TEXT,
+            submitter TEXT,
+            views INTEGER,
+            likes INTEGER,
+            downloads INTEGER,
+            created_date INTEGER,
+            modified_date INTEGER,
+            screenshots TEXT NOT NULL,
+            description TEXT,
+            text TEXT,
+            everest_modules TEXT NOT NULL DEFAULT '[]'
+        );",
+        (),
+    )
+    .await?;
+
+    add_column(conn, "maps", "gamebanana_id", "INTEGER").await?;
+    add_column(
+        conn,
+        "mods",
+        "everest_modules",
+        "TEXT NOT NULL DEFAULT '[]'",
+    )
+    .await?;
+    Ok(())
+}
+
+async fn add_column(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut columns = HashSet::new();
+    let mut rows = conn
+        .query(&format!("PRAGMA table_info({});", table), ())
+        .await?;
+    while let Some(row) = rows.next().await? {
+        columns.insert(row.get::<String>(1)?);
+    }
+    if !columns.contains(column) {
+        conn.execute(
+            &format!(
+                "ALTER TABLE {} ADD COLUMN {} {};",
+                table, column, definition
+            ),
+            (),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+// The database counts are far below i64::MAX, anything else is dropped rather than wrapped.
+fn to_i64(value: Option<u64>) -> Option<i64> {
+    value.and_then(|value| i64::try_from(value).ok())
+}
+
+async fn upload_mod(conn: &Connection, mod_detail: &ModDetail) -> Result<(), Box<dyn Error>> {
+    conn.execute(
+        "INSERT OR REPLACE INTO mods(
+            gamebanana_id, name, category, author, submitter, views, likes, downloads,
+            created_date, modified_date, screenshots, description, text, everest_modules
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14);",
+        (
+            mod_detail.gamebanana_id as i64,
+            mod_detail.name.clone(),
+            mod_detail.category_name.clone(),
+            mod_detail.author.clone(),
+            mod_detail.submitter.clone(),
+            to_i64(mod_detail.views),
+            to_i64(mod_detail.likes),
+            to_i64(mod_detail.downloads),
+            to_i64(mod_detail.created_date),
+            to_i64(mod_detail.modified_date),
+            serde_json::to_string(&mod_detail.screenshots)?,
+            mod_detail.description.clone(),
+            mod_detail.text.clone(),
+            serde_json::to_string(&mod_detail.everest_modules)?,
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+pub fn upload_stats(config: &Config) -> Result<(), Box<dyn Error>> {
     // https://nunomaduro.com/load_environment_variables_from_dotenv_files_in_your_rust_program
     dotenv().ok();
 
-    let mods_list: Vec<ModDetail> =
-        serde_yaml::from_str(&fs::read_to_string("mods/mods_list.yaml").unwrap()).unwrap();
+    // With the mods which are only in everest_update.yaml when downloading with that index.
+    let mods_list = cached_mods(config)?;
     let mut maps = vec![];
+    // Mods with at least one map, their details are uploaded next to the maps.
+    let mut map_mods = vec![];
+    let mut violations = vec![];
 
+    // Mods pruned by extract --prune are read from the store.
+    let mod_files = ModFiles::new(&config.paths, &config.zip)?;
     for mod_detail in mods_list {
-        let zip_archive = File::open(format!("mods/{}.zip", mod_detail.gamebanana_id))
-            .map(zip::ZipArchive::new);
-        if let Ok(Ok(mut zip_archive)) = zip_archive {
-            for i in 0..zip_archive.len() {
-                let file = zip_archive.by_index(i).unwrap();
-                if file.is_file()
-                    && file.enclosed_name().and_then(|name| name.extension())
-                        == Some(OsStr::new("bin"))
-                {
-                    let file_name = file
-                        .enclosed_name()
-                        .unwrap()
-                        .to_str()
-                        .unwrap()
-                        .split('/')
-                        .last()
-                        .unwrap()
-                        .to_string();
-                    let file_content = file.bytes().map(|byte| byte.unwrap()).collect::<Vec<_>>();
-                    if let Ok(map) = parse(&file_content) {
-                        maps.push((
-                            format!("{} / {}", mod_detail.name, file_name),
-                            room_details(&map).unwrap(),
-                            bounding_box(&map).unwrap(),
-                        ));
-                    }
-                }
+        let map_files = match mod_files.maps(mod_detail.gamebanana_id) {
+            Ok(Some(map_files)) => map_files,
+            Ok(None) => continue,
+            Err(err) => {
+                eprintln!(
+                    "Mod {}({}) - {}",
+                    mod_detail.name, mod_detail.gamebanana_id, err
+                );
+                violations.push(ZipViolation {
+                    gamebanana_id: mod_detail.gamebanana_id,
+                    name: mod_detail.name.clone(),
+                    error: err.to_string(),
+                });
+                continue;
             }
+        };
+        let number_of_maps = maps.len();
+        map_rows(
+            &mod_detail.name,
+            Some(mod_detail.gamebanana_id),
+            map_files,
+            &mut maps,
+        );
+        if maps.len() > number_of_maps {
+            map_mods.push(mod_detail);
+        }
+    }
+
+    // Vanilla maps and local mods, which aren't in the mod database.
+    if let Some(celeste_dir) = &config.paths.celeste_dir {
+        let mut rejected = vec![];
+        for installed_mod in scan_install(celeste_dir, &config.zip, &mut rejected)? {
+            map_rows(&installed_mod.name, None, installed_mod.maps, &mut maps);
+        }
+        for (path, err) in rejected {
+            eprintln!("{}: {}", path.display(), err);
         }
     }
 
-    let rt = Runtime::new().unwrap();
+    fs::create_dir_all(&config.paths.output_dir)?;
+    fs::write(
+        config.paths.zip_violations(),
+        serde_json::to_string_pretty(&violations)?,
+    )?;
+    if !violations.is_empty() {
+        println!(
+            "{} mods were skipped, see {}.",
+            violations.len(),
+            config.paths.zip_violations().display()
+        );
+    }
+
+    let rt = Runtime::new()?;
     rt.block_on(async {
-        let url = env::var("LIBSQL_URL").expect("LIBSQL_URL must be set.");
-        let token = env::var("LIBSQL_AUTH_TOKEN").unwrap_or_default();
+        let url = match &config.upload.database_url {
+            Some(url) => url.clone(),
+            None => env::var("LIBSQL_URL").map_err(|_| "LIBSQL_URL must be set.")?,
+        };
+        let token = match &config.upload.auth_token {
+            Some(token) => token.clone(),
+            None => env::var("LIBSQL_AUTH_TOKEN").unwrap_or_default(),
+        };
 
-        let db = Builder::new_remote(url, token).build().await.unwrap();
-        let conn = db.connect().unwrap();
+        let db = Builder::new_remote(url, token).build().await?;
+        let conn = db.connect()?;
+        migrate(&conn).await?;
 
-        for (name, room_detail, bounding_box) in maps {
+        for mod_detail in &map_mods {
+            upload_mod(&conn, mod_detail).await?;
+        }
+
+        for map in maps {
             let mut stmt = conn
-                .prepare("INSERT INTO maps(name, map, width, height) VALUES (?1, ?2, ?3, ?4);")
-                .await
-                .unwrap();
+                .prepare(
+                    "INSERT INTO maps(name, map, width, height, gamebanana_id) VALUES (?1, ?2, ?3, ?4, ?5);",
+                )
+                .await?;
             stmt.execute((
-                name,
-                serde_json::to_string(&room_detail).unwrap(),
-                bounding_box.width,
-                bounding_box.height,
+                map.name,
+                serde_json::to_string(&map.room_details)?,
+                map.bounding_box.width,
+                map.bounding_box.height,
+                map.gamebanana_id.map(|id| id as i64),
             ))
-            .await
-            .unwrap();
+            .await?;
         }
-    });
+
+        Ok(())
+    })
 }
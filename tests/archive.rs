@@ -0,0 +1,123 @@
+use std::{io::Write, path::Path};
+
+use celeste_maps_data::{
+    archive::{read_map_files, ArchiveError, SafeZip},
+    config::ZipLimits,
+};
+
+fn stored_zip(entries: &[(&str, &str)]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for (name, content) in entries {
+        zip.start_file(*name, options).unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+fn replace(bytes: &mut [u8], from: &[u8], to: &[u8]) {
+    let start = bytes
+        .windows(from.len())
+        .position(|window| window == from)
+        .unwrap();
+    bytes[start..start + to.len()].copy_from_slice(to);
+}
+
+// Sets the encryption bit in the local and central headers, as ZipCrypto does.
+fn mark_encrypted(bytes: &mut [u8]) {
+    for (signature, flags_offset) in [(b"PK\x03\x04", 6), (b"PK\x01\x02", 8)] {
+        let start = bytes
+            .windows(4)
+            .position(|window| window == signature)
+            .unwrap();
+        bytes[start + flags_offset] |= 1;
+    }
+}
+
+fn write(dir: &Path, name: &str, bytes: &[u8]) -> std::path::PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, bytes).unwrap();
+    path
+}
+
+#[test]
+fn reads_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write(
+        dir.path(),
+        "mod.zip",
+        &stored_zip(&[("Maps/a.bin", "map"), ("everest.yaml", "")]),
+    );
+
+    let mut zip = SafeZip::open(&path, &ZipLimits::default()).unwrap();
+    let index = zip.find(|name| name == "Maps/a.bin").unwrap();
+    assert_eq!(zip.read(index).unwrap(), b"map");
+}
+
+#[test]
+fn detects_crc_mismatches() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut bytes = stored_zip(&[("Maps/a.bin", "hello world")]);
+    replace(&mut bytes, b"hello world", b"hellO world");
+    let path = write(dir.path(), "mod.zip", &bytes);
+
+    let mut zip = SafeZip::open(&path, &ZipLimits::default()).unwrap();
+    let err = zip.read(0).unwrap_err();
+    assert!(
+        matches!(&err, ArchiveError::CrcMismatch { entry } if entry == "Maps/a.bin"),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn rejects_encrypted_entries_when_opening() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut bytes = stored_zip(&[("Maps/a.bin", "map")]);
+    mark_encrypted(&mut bytes);
+    let path = write(dir.path(), "mod.zip", &bytes);
+
+    let err = SafeZip::open(&path, &ZipLimits::default()).err().unwrap();
+    assert!(
+        matches!(&err, ArchiveError::Encrypted { entry } if entry == "Maps/a.bin"),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn directories_report_rejected_zips() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "a.zip", &stored_zip(&[("Maps/a.bin", "map")]));
+    let mut encrypted = stored_zip(&[("Maps/b.bin", "map")]);
+    mark_encrypted(&mut encrypted);
+    let encrypted_path = write(dir.path(), "b.zip", &encrypted);
+
+    let mut rejected = vec![];
+    let maps = read_map_files(dir.path(), &ZipLimits::default(), &mut rejected).unwrap();
+
+    assert_eq!(maps.len(), 1);
+    assert!(maps[0].name.ends_with("a.zip/Maps/a.bin"));
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].0, encrypted_path);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn validate_fails_for_rejected_zips() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut encrypted = stored_zip(&[("Maps/b.bin", "map")]);
+    mark_encrypted(&mut encrypted);
+    write(dir.path(), "b.zip", &encrypted);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_celeste-maps-data"))
+        .arg("validate")
+        .arg(dir.path())
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("1 zips were rejected."));
+}
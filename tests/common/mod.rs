@@ -0,0 +1,311 @@
+// Shared by the integration tests, not every test uses every helper.
+#![allow(dead_code)]
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use celeste_maps_data::{
+    config::Config,
+    download::{
+        checksum::xx_hash,
+        progress::{ProgressEvent, ProgressReporter},
+        FileDetails, ModDetail,
+    },
+};
+
+// Mods and output in a temporary directory, retrying quickly.
+pub fn test_config(dir: &Path) -> Config {
+    let mut config = Config::default();
+    config.paths.mods_dir = dir.join("mods");
+    config.paths.output_dir = dir.join("output");
+    config.paths.store_dir = dir.join("store");
+    config.download.retries = 3;
+    config.download.retry_base_delay_ms = 1;
+    config.download.retry_max_delay_secs = 1;
+    config.download.timeout_secs = 10;
+    config
+}
+
+// A mod zip with one map, the content tells mods apart.
+pub fn mod_zip(content: &str) -> Vec<u8> {
+    zip_with(&[("Maps/test.bin", content)])
+}
+
+pub fn zip_with(entries: &[(&str, &str)]) -> Vec<u8> {
+    let entries = entries
+        .iter()
+        .map(|(name, content)| (*name, content.as_bytes()))
+        .collect::<Vec<_>>();
+    zip_with_bytes(&entries)
+}
+
+// With a fixed modification time, so the same entries always make the same zip.
+pub fn zip_with_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    let options = zip::write::FileOptions::default().last_modified_time(zip::DateTime::default());
+    for (name, content) in entries {
+        zip.start_file(*name, options).unwrap();
+        zip.write_all(content).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+pub fn file(url: &str, created_date: u64, bytes: &[u8]) -> FileDetails {
+    FileDetails {
+        url: url.to_string(),
+        created_date,
+        xx_hash: vec![format!("{:016x}", xx_hash(bytes))],
+        size: Some(bytes.len() as u64),
+        ..FileDetails::default()
+    }
+}
+
+pub fn map_mod(gamebanana_id: u64, name: &str, files: Vec<FileDetails>) -> ModDetail {
+    ModDetail {
+        name: name.to_string(),
+        gamebanana_id,
+        files,
+        category_name: "Maps".to_string(),
+        ..ModDetail::default()
+    }
+}
+
+// Keeps every event as JSON so tests can count them.
+#[derive(Debug, Default)]
+pub struct RecordedProgress {
+    pub events: Mutex<Vec<serde_json::Value>>,
+}
+
+impl RecordedProgress {
+    pub fn count(&self, event: &str, gamebanana_id: u64) -> usize {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|recorded| recorded["event"] == event)
+            .filter(|recorded| recorded["gamebanana_id"] == gamebanana_id)
+            .count()
+    }
+}
+
+impl ProgressReporter for RecordedProgress {
+    fn report(&self, event: &ProgressEvent) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(serde_json::to_value(event).unwrap());
+    }
+}
+
+pub fn read_json(path: &Path) -> serde_json::Value {
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+// How the next request of a path fails.
+#[derive(Debug, Clone, Copy)]
+pub enum Failure {
+    Status(u16),
+    // Sends the headers of the whole file, then closes the connection after this many body bytes.
+    DropAfter(usize),
+    // Answers a range request with the whole file as 206, claiming it starts at 0.
+    WrongRange,
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub path: String,
+    pub range: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    files: HashMap<String, Vec<u8>>,
+    failures: HashMap<String, VecDeque<Failure>>,
+    requests: Vec<Request>,
+    // Body bytes per second of every response, unlimited when None.
+    rate: Option<u64>,
+}
+
+// A small HTTP/1.1 server on localhost standing in for GameBanana and the mirrors. It serves
+// ranges, and failures can be queued per path.
+#[derive(Debug, Clone)]
+pub struct StandInServer {
+    address: String,
+    state: Arc<Mutex<ServerState>>,
+}
+
+impl StandInServer {
+    pub fn start() -> StandInServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = StandInServer {
+            address: format!("http://{}", listener.local_addr().unwrap()),
+            state: Arc::default(),
+        };
+        let state = server.state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let state = state.clone();
+                thread::spawn(move || handle(stream, &state));
+            }
+        });
+        server
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.address, path)
+    }
+
+    pub fn file(&self, path: &str, bytes: Vec<u8>) -> &StandInServer {
+        self.state
+            .lock()
+            .unwrap()
+            .files
+            .insert(path.to_string(), bytes);
+        self
+    }
+
+    pub fn fail_next(&self, path: &str, failure: Failure) -> &StandInServer {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .entry(path.to_string())
+            .or_default()
+            .push_back(failure);
+        self
+    }
+
+    pub fn set_rate(&self, bytes_per_second: u64) {
+        self.state.lock().unwrap().rate = Some(bytes_per_second);
+    }
+
+    pub fn requests(&self, path: &str) -> Vec<Request> {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter(|request| request.path == path)
+            .cloned()
+            .collect()
+    }
+}
+
+fn handle(mut stream: TcpStream, state: &Mutex<ServerState>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+    let mut range = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).is_err() || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("range") {
+                range = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let (file, failure, rate) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(Request {
+            path: path.clone(),
+            range: range.clone(),
+        });
+        let failure = state.failures.get_mut(&path).and_then(VecDeque::pop_front);
+        (state.files.get(&path).cloned(), failure, state.rate)
+    };
+
+    let Some(file) = file else {
+        return respond_status(&mut stream, 404);
+    };
+    let start = match failure {
+        Some(Failure::Status(status)) => return respond_status(&mut stream, status),
+        Some(Failure::DropAfter(_)) => 0,
+        Some(Failure::WrongRange) => return respond_wrong_range(&mut stream, &file),
+        None => range
+            .as_deref()
+            .and_then(|range| range.strip_prefix("bytes="))
+            .and_then(|range| range.strip_suffix('-'))
+            .and_then(|start| start.parse::<usize>().ok())
+            .unwrap_or(0),
+    };
+    if start > 0 && start >= file.len() {
+        return respond_status(&mut stream, 416);
+    }
+
+    let mut head = if start > 0 {
+        format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n",
+            start,
+            file.len() - 1,
+            file.len()
+        )
+    } else {
+        "HTTP/1.1 200 OK\r\n".to_string()
+    };
+    head += &format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        file.len() - start
+    );
+    if stream.write_all(head.as_bytes()).is_err() {
+        return;
+    }
+
+    let body = match failure {
+        Some(Failure::DropAfter(bytes)) => &file[..bytes.min(file.len())],
+        _ => &file[start..],
+    };
+    let chunk_size = rate.map_or(body.len().max(1), |rate| (rate as usize / 20).max(1));
+    for chunk in body.chunks(chunk_size) {
+        if stream.write_all(chunk).is_err() {
+            return;
+        }
+        if rate.is_some() {
+            let _ = stream.flush();
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+    let _ = stream.flush();
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+fn respond_wrong_range(stream: &mut TcpStream, file: &[u8]) {
+    let _ = write!(
+        stream,
+        "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        file.len() - 1,
+        file.len(),
+        file.len()
+    );
+    let _ = stream.write_all(file);
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+fn respond_status(stream: &mut TcpStream, status: u16) {
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} Stand-in\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    );
+    let _ = stream.shutdown(Shutdown::Both);
+}
@@ -0,0 +1,550 @@
+#![cfg(feature = "download")]
+
+mod common;
+
+use std::sync::Arc;
+
+use celeste_maps_data::{
+    config::Config,
+    download::{
+        checksum::xx_hash, download_maps_with, error::DownloadError, history::FileHistory,
+        source::MemorySource, DownloadOptions,
+    },
+};
+use common::{file, map_mod, mod_zip, read_json, test_config, zip_with, RecordedProgress};
+use reqwest::StatusCode;
+
+const A_URL: &str = "https://gamebanana.com/mmdl/1";
+const B_URL: &str = "https://gamebanana.com/mmdl/2";
+const TOOL_URL: &str = "https://gamebanana.com/mmdl/3";
+
+fn two_maps_and_a_tool() -> MemorySource {
+    let a = mod_zip("a");
+    let b = mod_zip("b");
+    let tool = mod_zip("tool");
+    let mut tool_mod = map_mod(3, "Tool", vec![file(TOOL_URL, 1, &tool)]);
+    tool_mod.category_name = "Tools".to_string();
+    MemorySource::new(vec![
+        map_mod(1, "A", vec![file(A_URL, 1, &a)]),
+        map_mod(2, "B", vec![file(B_URL, 1, &b)]),
+        tool_mod,
+    ])
+    .with_file(A_URL, a)
+    .with_file(B_URL, b)
+    .with_file(TOOL_URL, tool)
+}
+
+#[test]
+fn downloads_selected_mods() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let source = Arc::new(two_maps_and_a_tool());
+    let progress = Arc::new(RecordedProgress::default());
+
+    let failures = download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        source.clone(),
+        progress.clone(),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 0);
+    assert_eq!(
+        std::fs::read(config.paths.mod_zip(1)).unwrap(),
+        mod_zip("a")
+    );
+    assert!(config.paths.mod_zip(2).is_file());
+    assert!(!config.paths.mod_zip(3).exists());
+    assert_eq!(source.fetches(TOOL_URL), 0);
+    assert_eq!(progress.count("finished", 1), 1);
+    assert_eq!(progress.count("finished", 2), 1);
+
+    let state = read_json(&config.paths.download_state());
+    assert_eq!(state["mods"]["1"]["status"], "downloaded");
+    assert_eq!(state["mods"]["1"]["attempts"], 1);
+}
+
+#[test]
+fn skips_downloaded_mods() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let source = Arc::new(two_maps_and_a_tool());
+    let progress = Arc::new(RecordedProgress::default());
+
+    for _ in 0..2 {
+        let failures = download_maps_with(
+            &config,
+            &DownloadOptions::default(),
+            source.clone(),
+            progress.clone(),
+        )
+        .unwrap();
+        assert_eq!(failures, 0);
+    }
+
+    assert_eq!(source.fetches(A_URL), 1);
+    assert_eq!(source.fetches(B_URL), 1);
+    assert_eq!(progress.count("queued", 1), 1);
+    let state = read_json(&config.paths.download_state());
+    assert_eq!(state["mods"]["1"]["status"], "downloaded");
+    assert_eq!(state["mods"]["1"]["skip_reason"], "Already downloaded");
+}
+
+#[test]
+fn zips_from_before_the_state_count_as_downloaded() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    std::fs::create_dir_all(&config.paths.mods_dir).unwrap();
+    let a = mod_zip("a");
+    std::fs::write(config.paths.mod_zip(1), &a).unwrap();
+    let source = Arc::new(two_maps_and_a_tool());
+
+    download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        source.clone(),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    assert_eq!(source.fetches(A_URL), 0);
+    let state = read_json(&config.paths.download_state());
+    assert_eq!(state["mods"]["1"]["status"], "downloaded");
+    assert_eq!(state["mods"]["1"]["size"], a.len());
+    assert_eq!(
+        state["mods"]["1"]["xx_hash"],
+        format!("{:016x}", xx_hash(&a))
+    );
+    assert_eq!(state["mods"]["1"]["skip_reason"], "Already downloaded");
+}
+
+#[test]
+fn retries_transient_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let source = Arc::new(two_maps_and_a_tool());
+    source.fail_next(A_URL, DownloadError::Timeout);
+    source.fail_next(
+        A_URL,
+        DownloadError::Http {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            retry_after: None,
+        },
+    );
+    let progress = Arc::new(RecordedProgress::default());
+
+    let failures = download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        source.clone(),
+        progress.clone(),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 0);
+    assert_eq!(source.fetches(A_URL), 3);
+    assert_eq!(progress.count("retried", 1), 2);
+    assert_eq!(progress.count("started", 1), 3);
+    assert!(config.paths.mod_zip(1).is_file());
+    let state = read_json(&config.paths.download_state());
+    assert_eq!(state["mods"]["1"]["attempts"], 3);
+}
+
+#[test]
+fn gives_up_after_the_last_attempt() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let source = Arc::new(two_maps_and_a_tool());
+    for _ in 0..config.download.retries {
+        source.fail_next(A_URL, DownloadError::Timeout);
+    }
+
+    let failures = download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        source.clone(),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 1);
+    assert_eq!(source.fetches(A_URL), config.download.retries as usize);
+    assert!(!config.paths.mod_zip(1).exists());
+    assert!(config.paths.mod_zip(2).is_file());
+    let state = read_json(&config.paths.download_state());
+    assert_eq!(state["mods"]["1"]["status"], "failed");
+    assert_eq!(state["mods"]["1"]["last_error_kind"], "transient");
+}
+
+#[test]
+fn permanent_errors_are_not_retried() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let source = Arc::new(two_maps_and_a_tool());
+    source.fail_next(
+        A_URL,
+        DownloadError::Http {
+            status: StatusCode::NOT_FOUND,
+            retry_after: None,
+        },
+    );
+
+    let failures = download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        source.clone(),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 1);
+    assert_eq!(source.fetches(A_URL), 1);
+    let failed = read_json(&config.paths.download_failures());
+    assert_eq!(failed[0]["gamebanana_id"], 1);
+
+    // Only the failed mod is tried again.
+    let retry_failed = DownloadOptions {
+        retry_failed: true,
+        ..DownloadOptions::default()
+    };
+    let failures = download_maps_with(
+        &config,
+        &retry_failed,
+        source.clone(),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 0);
+    assert_eq!(source.fetches(A_URL), 2);
+    assert_eq!(source.fetches(B_URL), 1);
+    assert!(config.paths.mod_zip(1).is_file());
+}
+
+#[test]
+fn quarantines_checksum_mismatches() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let expected = mod_zip("a");
+    let source = Arc::new(
+        MemorySource::new(vec![map_mod(1, "A", vec![file(A_URL, 1, &expected)])])
+            .with_file(A_URL, mod_zip("corrupted")),
+    );
+
+    let failures = download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        source.clone(),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 1);
+    assert_eq!(source.fetches(A_URL), config.download.retries as usize);
+    assert!(!config.paths.mod_zip(1).exists());
+    assert_eq!(
+        std::fs::read(config.paths.quarantined_zip(1)).unwrap(),
+        mod_zip("corrupted")
+    );
+}
+
+#[test]
+fn refresh_downloads_updated_mods() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        Arc::new(two_maps_and_a_tool()),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    let new_url = "https://gamebanana.com/mmdl/4";
+    let a = mod_zip("a");
+    let new_a = mod_zip("new a");
+    let b = mod_zip("b");
+    let source = Arc::new(
+        MemorySource::new(vec![
+            map_mod(1, "A", vec![file(A_URL, 1, &a), file(new_url, 2, &new_a)]),
+            map_mod(2, "B", vec![file(B_URL, 1, &b)]),
+        ])
+        .with_file(new_url, new_a.clone()),
+    );
+    let refresh = DownloadOptions {
+        refresh: true,
+        ..DownloadOptions::default()
+    };
+    let failures = download_maps_with(
+        &config,
+        &refresh,
+        source.clone(),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 0);
+    assert_eq!(source.fetches(new_url), 1);
+    assert_eq!(source.fetches(B_URL), 0);
+    assert_eq!(std::fs::read(config.paths.mod_zip(1)).unwrap(), new_a);
+}
+
+#[test]
+fn refresh_tells_removed_and_deselected_mods_apart() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        Arc::new(two_maps_and_a_tool()),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    // A is now a tool, B left the database.
+    let a = mod_zip("a");
+    let mut a_mod = map_mod(1, "A", vec![file(A_URL, 1, &a)]);
+    a_mod.category_name = "Tools".to_string();
+    let refresh = DownloadOptions {
+        refresh: true,
+        ..DownloadOptions::default()
+    };
+    download_maps_with(
+        &config,
+        &refresh,
+        Arc::new(MemorySource::new(vec![a_mod])),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    let state = read_json(&config.paths.download_state());
+    assert_eq!(state["mods"]["1"]["skip_reason"], "No longer selected");
+    assert_eq!(state["mods"]["2"]["skip_reason"], "Not in the mod database");
+    assert!(config.paths.mod_zip(1).is_file());
+    assert!(config.paths.mod_zip(2).is_file());
+}
+
+#[test]
+fn refresh_updates_dependencies() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let map_url = "https://gamebanana.com/mmdl/10";
+    let helper_url = "https://gamebanana.com/mmdl/20";
+    let new_helper_url = "https://gamebanana.com/mmdl/21";
+    let map = zip_with(&[
+        ("Maps/test.bin", "map"),
+        (
+            "everest.yaml",
+            "- Name: Map\n  Version: 1.0.0\n  Dependencies:\n    - Name: Helper\n      Version: 1.0.0\n",
+        ),
+    ]);
+    let helper = zip_with(&[("everest.yaml", "- Name: Helper\n  Version: 1.0.0\n")]);
+    let new_helper = zip_with(&[("everest.yaml", "- Name: Helper\n  Version: 1.1.0\n")]);
+    let everest_update = "
+Map:
+  GameBananaType: Mod
+  GameBananaId: 1
+Helper:
+  GameBananaType: Mod
+  GameBananaId: 2
+";
+    let source = |helper_files| {
+        let mut helper_mod = map_mod(2, "Helper", helper_files);
+        helper_mod.category_name = "Helpers".to_string();
+        Arc::new(
+            MemorySource::new(vec![
+                map_mod(1, "Map", vec![file(map_url, 1, &map)]),
+                helper_mod,
+            ])
+            .with_everest_update(everest_update.to_string())
+            .with_file(map_url, map.clone())
+            .with_file(helper_url, helper.clone())
+            .with_file(new_helper_url, new_helper.clone()),
+        )
+    };
+    let with_dependencies = DownloadOptions {
+        dependencies: true,
+        ..DownloadOptions::default()
+    };
+    download_maps_with(
+        &config,
+        &with_dependencies,
+        source(vec![file(helper_url, 1, &helper)]),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    let refreshed = source(vec![
+        file(helper_url, 1, &helper),
+        file(new_helper_url, 2, &new_helper),
+    ]);
+    let refresh = DownloadOptions {
+        refresh: true,
+        ..with_dependencies
+    };
+    let failures = download_maps_with(
+        &config,
+        &refresh,
+        refreshed.clone(),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 0);
+    assert_eq!(refreshed.fetches(new_helper_url), 1);
+    assert_eq!(refreshed.fetches(map_url), 0);
+    assert_eq!(std::fs::read(config.paths.mod_zip(2)).unwrap(), new_helper);
+    // Followed as a dependency, so still wanted.
+    let state = read_json(&config.paths.download_state());
+    assert_ne!(state["mods"]["2"]["skip_reason"], "No longer selected");
+}
+
+const OLD_URL: &str = "https://gamebanana.com/mmdl/11";
+const NEW_URL: &str = "https://gamebanana.com/mmdl/12";
+
+// A mod with an older and a newer file.
+fn mod_with_two_files() -> MemorySource {
+    let old = mod_zip("old");
+    let new = mod_zip("new");
+    MemorySource::new(vec![map_mod(
+        1,
+        "A",
+        vec![file(OLD_URL, 1, &old), file(NEW_URL, 2, &new)],
+    )])
+    .with_file(OLD_URL, old)
+    .with_file(NEW_URL, new)
+}
+
+fn download_without_failures(config: &Config, source: &Arc<MemorySource>) {
+    let failures = download_maps_with(
+        config,
+        &DownloadOptions::default(),
+        source.clone(),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+    assert_eq!(failures, 0);
+}
+
+#[test]
+fn all_files_downloads_every_file_of_a_mod() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = test_config(dir.path());
+    config.download.all_files = true;
+    let source = Arc::new(mod_with_two_files());
+
+    download_without_failures(&config, &source);
+
+    let old = mod_zip("old");
+    let new = mod_zip("new");
+    assert_eq!(std::fs::read(config.paths.mod_zip(1)).unwrap(), new);
+    assert_eq!(
+        std::fs::read(config.paths.mod_file_zip(1, "11")).unwrap(),
+        old
+    );
+    assert_eq!(
+        std::fs::read(config.paths.mod_file_zip(1, "12")).unwrap(),
+        new
+    );
+    // The latest file is linked from mods/1.zip.
+    assert_eq!(source.fetches(OLD_URL), 1);
+    assert_eq!(source.fetches(NEW_URL), 1);
+
+    let history = FileHistory::load(&config.paths.mod_file_history(1)).unwrap();
+    assert_eq!(
+        history.files.keys().collect::<Vec<_>>(),
+        ["11", "12"].iter().collect::<Vec<_>>()
+    );
+    let entry = &history.files["11"];
+    assert_eq!(entry.url, OLD_URL);
+    assert_eq!(entry.created_date, 1);
+    assert_eq!(entry.size, Some(old.len() as u64));
+    assert_eq!(entry.xx_hash, [format!("{:016x}", xx_hash(&old))]);
+    assert_eq!(history.files["12"].url, NEW_URL);
+}
+
+#[test]
+fn all_files_links_the_latest_file_of_downloaded_mods() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = test_config(dir.path());
+    let source = Arc::new(mod_with_two_files());
+    download_without_failures(&config, &source);
+    assert_eq!(source.fetches(NEW_URL), 1);
+    assert_eq!(source.fetches(OLD_URL), 0);
+
+    config.download.all_files = true;
+    download_without_failures(&config, &source);
+    download_without_failures(&config, &source);
+
+    assert_eq!(source.fetches(NEW_URL), 1);
+    assert_eq!(source.fetches(OLD_URL), 1);
+    assert!(config.paths.mod_file_zip(1, "12").is_file());
+    let history = FileHistory::load(&config.paths.mod_file_history(1)).unwrap();
+    assert_eq!(history.files.len(), 2);
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let source = Arc::new(two_maps_and_a_tool());
+    let dry_run = DownloadOptions {
+        dry_run: true,
+        ..DownloadOptions::default()
+    };
+
+    let failures = download_maps_with(
+        &config,
+        &dry_run,
+        source.clone(),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 0);
+    assert_eq!(source.fetches(A_URL), 0);
+    assert!(!config.paths.mod_zip(1).exists());
+    assert!(!config.paths.download_state().exists());
+}
+
+#[test]
+fn unknown_sizes_stop_when_the_budget_is_used_up() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = test_config(dir.path());
+    config.budget.max_total_size_mb = Some(1);
+    let urls = [A_URL, B_URL, TOOL_URL];
+    let mut mods = vec![];
+    let mut source_files = vec![];
+    for (id, url) in (1..).zip(urls) {
+        // Not a zip, only the size matters here.
+        let bytes = vec![id as u8; 600 * 1024];
+        let mut unknown_size = file(url, 1, &bytes);
+        unknown_size.size = None;
+        mods.push(map_mod(id, &format!("Mod {}", id), vec![unknown_size]));
+        source_files.push((url, bytes));
+    }
+    let mut source = MemorySource::new(mods);
+    for (url, bytes) in source_files {
+        source = source.with_file(url, bytes);
+    }
+    let source = Arc::new(source);
+
+    let failures = download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        source.clone(),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    // The second file starts below the budget and goes over it, the third one doesn't start.
+    assert_eq!(failures, 0);
+    let fetches = urls.iter().map(|url| source.fetches(url)).sum::<usize>();
+    assert_eq!(fetches, 2);
+    let state = read_json(&config.paths.download_state());
+    let skipped = (1..=3)
+        .filter(|id| state["mods"][id.to_string()]["status"] == "skipped")
+        .count();
+    assert_eq!(skipped, 1);
+}
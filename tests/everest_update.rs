@@ -0,0 +1,157 @@
+#![cfg(feature = "download")]
+
+mod common;
+
+use std::sync::Arc;
+
+use celeste_maps_data::{
+    config::ModIndex,
+    download::{
+        cached_mods,
+        checksum::audit_mods,
+        download_maps_with,
+        everest_update::ModuleIndex,
+        select::{Selection, UNCATEGORIZED},
+        source::MemorySource,
+        DownloadOptions, FileDetails,
+    },
+};
+use common::{map_mod, mod_zip, test_config, RecordedProgress};
+
+const LISTED_URL: &str = "https://gamebanana.com/mmdl/1";
+const UNLISTED_URL: &str = "https://gamebanana.com/mmdl/2";
+
+fn everest_update(listed: &[u8], unlisted: &[u8]) -> String {
+    let hash = |bytes: &[u8]| {
+        format!(
+            "{:016x}",
+            celeste_maps_data::download::checksum::xx_hash(bytes)
+        )
+    };
+    format!(
+        "
+Listed:
+  GameBananaType: Mod
+  GameBananaId: 1
+  Version: 1.0.0
+  URL: {LISTED_URL}
+  xxHash: ['{}']
+  Size: {}
+Unlisted:
+  GameBananaType: Mod
+  GameBananaId: 2
+  Version: 1.0.0
+  URL: {UNLISTED_URL}
+  xxHash: ['{}']
+  Size: {}
+",
+        hash(listed),
+        listed.len(),
+        hash(unlisted),
+        unlisted.len()
+    )
+}
+
+// The database lists mod 1 without checksums or size, mod 2 is only in everest_update.yaml.
+fn database() -> Vec<celeste_maps_data::download::ModDetail> {
+    vec![map_mod(
+        1,
+        "Listed",
+        vec![FileDetails {
+            url: LISTED_URL.to_string(),
+            created_date: 1,
+            ..FileDetails::default()
+        }],
+    )]
+}
+
+#[test]
+fn merge_fills_in_checksums_and_sizes() {
+    let listed = mod_zip("listed");
+    let index = ModuleIndex::from_everest_update(&everest_update(&listed, b"")).unwrap();
+
+    let mods = index.merge(&database());
+
+    let file = &mods[0].files[0];
+    assert_eq!(file.size, Some(listed.len() as u64));
+    assert_eq!(file.xx_hash.len(), 1);
+    assert_eq!(mods[0].category_name, "Maps");
+}
+
+#[test]
+fn unlisted_mods_are_uncategorized() {
+    let index = ModuleIndex::from_everest_update(&everest_update(b"", b"")).unwrap();
+
+    let mods = index.merge(&database());
+
+    assert_eq!(mods[1].name, "Unlisted");
+    assert_eq!(mods[1].category_name, UNCATEGORIZED);
+
+    let mut config = test_config(std::path::Path::new("."));
+    assert!(Selection::new(&config.download).unwrap().selects(&mods[1]));
+    config.download.exclude_categories = vec![UNCATEGORIZED.to_string()];
+    assert!(!Selection::new(&config.download).unwrap().selects(&mods[1]));
+}
+
+#[test]
+fn downloads_every_mod_of_the_everest_update_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = test_config(dir.path());
+    config.download.index = ModIndex::EverestUpdate;
+    let listed = mod_zip("listed");
+    let unlisted = mod_zip("unlisted");
+    let source = Arc::new(
+        MemorySource::new(database())
+            .with_everest_update(everest_update(&listed, &unlisted))
+            // The checksum from everest_update.yaml catches the bad file.
+            .with_file(LISTED_URL, mod_zip("corrupted"))
+            .with_file(UNLISTED_URL, unlisted.clone()),
+    );
+
+    let failures = download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        source.clone(),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 1);
+    assert!(config.paths.quarantined_zip(1).is_file());
+    assert_eq!(std::fs::read(config.paths.mod_zip(2)).unwrap(), unlisted);
+}
+
+#[test]
+fn audits_mods_only_in_everest_update() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = test_config(dir.path());
+    config.download.index = ModIndex::EverestUpdate;
+    let listed = mod_zip("listed");
+    let unlisted = mod_zip("unlisted");
+    let source = Arc::new(
+        MemorySource::new(database())
+            .with_everest_update(everest_update(&listed, &unlisted))
+            .with_file(LISTED_URL, listed)
+            .with_file(UNLISTED_URL, unlisted),
+    );
+    download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        source,
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+    let mod_ids = cached_mods(&config)
+        .unwrap()
+        .iter()
+        .map(|mod_detail| mod_detail.gamebanana_id)
+        .collect::<Vec<_>>();
+    assert_eq!(mod_ids, [1, 2]);
+
+    std::fs::write(config.paths.mod_zip(2), mod_zip("corrupted")).unwrap();
+    let report = audit_mods(&config).unwrap();
+
+    assert_eq!(report.verified, 1);
+    assert_eq!(report.quarantined.len(), 1);
+    assert_eq!(report.quarantined[0].0, 2);
+}
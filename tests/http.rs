@@ -0,0 +1,97 @@
+#![cfg(feature = "download")]
+
+mod common;
+
+use std::sync::Arc;
+
+use celeste_maps_data::download::{
+    download_maps_with, source::HttpSource, DownloadOptions, ModDetail,
+};
+use common::{
+    file, map_mod, mod_zip, read_json, test_config, Failure, RecordedProgress, StandInServer,
+};
+
+fn serve_mods(server: &StandInServer, mods: &[ModDetail]) {
+    server.file(
+        "/mod_search_database.yaml",
+        serde_yaml::to_string(mods).unwrap().into_bytes(),
+    );
+}
+
+#[test]
+fn downloads_and_retries_over_http() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = StandInServer::start();
+    let mut config = test_config(dir.path());
+    config.download.database_url = server.url("/mod_search_database.yaml");
+
+    let a = mod_zip("a");
+    let b = mod_zip("b");
+    server.file("/mmdl/1", a.clone()).file("/mmdl/2", b.clone());
+    server
+        .fail_next("/mmdl/1", Failure::Status(503))
+        .fail_next("/mmdl/1", Failure::Status(500));
+    serve_mods(
+        &server,
+        &[
+            map_mod(1, "A", vec![file(&server.url("/mmdl/1"), 1, &a)]),
+            map_mod(2, "B", vec![file(&server.url("/mmdl/2"), 1, &b)]),
+        ],
+    );
+    let progress = Arc::new(RecordedProgress::default());
+
+    let failures = download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        Arc::new(HttpSource::new(&config.download).unwrap()),
+        progress.clone(),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 0);
+    assert_eq!(server.requests("/mmdl/1").len(), 3);
+    assert_eq!(server.requests("/mmdl/2").len(), 1);
+    assert_eq!(progress.count("retried", 1), 2);
+    assert_eq!(std::fs::read(config.paths.mod_zip(1)).unwrap(), a);
+    assert_eq!(std::fs::read(config.paths.mod_zip(2)).unwrap(), b);
+
+    // Nothing is requested for mods which are already downloaded.
+    let failures = download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        Arc::new(HttpSource::new(&config.download).unwrap()),
+        progress.clone(),
+    )
+    .unwrap();
+    assert_eq!(failures, 0);
+    assert_eq!(server.requests("/mmdl/1").len(), 3);
+    assert_eq!(server.requests("/mmdl/2").len(), 1);
+}
+
+#[test]
+fn missing_files_fail_without_retries() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = StandInServer::start();
+    let mut config = test_config(dir.path());
+    config.download.database_url = server.url("/mod_search_database.yaml");
+
+    let a = mod_zip("a");
+    serve_mods(
+        &server,
+        &[map_mod(1, "A", vec![file(&server.url("/mmdl/1"), 1, &a)])],
+    );
+
+    let failures = download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        Arc::new(HttpSource::new(&config.download).unwrap()),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 1);
+    assert_eq!(server.requests("/mmdl/1").len(), 1);
+    let state = read_json(&config.paths.download_state());
+    assert_eq!(state["mods"]["1"]["status"], "failed");
+    assert_eq!(state["mods"]["1"]["last_error_kind"], "permanent");
+}
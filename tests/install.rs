@@ -0,0 +1,121 @@
+use std::{fs, io::Write, path::Path};
+
+use celeste_maps_data::{
+    config::ZipLimits,
+    install::{scan_install, InstalledMod, ModKind, VANILLA},
+};
+
+fn zip_with(entries: &[(&str, &str)]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    for (name, content) in entries {
+        zip.start_file(*name, zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+fn write(path: &Path, bytes: &[u8]) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, bytes).unwrap();
+}
+
+fn map_names(installed_mod: &InstalledMod) -> Vec<&str> {
+    installed_mod
+        .maps
+        .iter()
+        .map(|map| map.name.as_str())
+        .collect()
+}
+
+#[test]
+fn scans_vanilla_maps_and_mods() {
+    let dir = tempfile::tempdir().unwrap();
+    let celeste_dir = dir.path();
+    write(&celeste_dir.join("Content/Maps/0-Intro.bin"), b"intro");
+    write(
+        &celeste_dir.join("Content/Maps/1-ForsakenCity.bin"),
+        b"city",
+    );
+    write(&celeste_dir.join("Content/Dialog/English.txt"), b"");
+    write(
+        &celeste_dir.join("Mods/Zipped.zip"),
+        &zip_with(&[("Maps/author/zipped.bin", "zipped"), ("everest.yaml", "")]),
+    );
+    write(
+        &celeste_dir.join("Mods/Unzipped/Maps/author/unzipped.bin"),
+        b"unzipped",
+    );
+    // Mods without maps are left out.
+    write(&celeste_dir.join("Mods/Helper/everest.yaml"), b"");
+    write(&celeste_dir.join("Mods/modoptionsorder.txt"), b"");
+
+    let mut rejected = vec![];
+    let installed_mods = scan_install(celeste_dir, &ZipLimits::default(), &mut rejected).unwrap();
+
+    assert!(rejected.is_empty());
+    assert_eq!(installed_mods.len(), 3);
+    assert_eq!(installed_mods[0].name, VANILLA);
+    assert_eq!(installed_mods[0].kind, ModKind::Vanilla);
+    assert_eq!(
+        map_names(&installed_mods[0]),
+        ["Maps/0-Intro.bin", "Maps/1-ForsakenCity.bin"]
+    );
+    assert_eq!(installed_mods[0].maps[0].bytes, b"intro");
+
+    assert_eq!(installed_mods[1].name, "Unzipped");
+    assert_eq!(installed_mods[1].kind, ModKind::Directory);
+    assert_eq!(map_names(&installed_mods[1]), ["Maps/author/unzipped.bin"]);
+
+    assert_eq!(installed_mods[2].name, "Zipped");
+    assert_eq!(installed_mods[2].kind, ModKind::Zip);
+    assert_eq!(map_names(&installed_mods[2]), ["Maps/author/zipped.bin"]);
+    assert_eq!(installed_mods[2].maps[0].bytes, b"zipped");
+}
+
+#[test]
+fn reads_the_content_of_the_macos_bundle() {
+    let dir = tempfile::tempdir().unwrap();
+    let celeste_dir = dir.path().join("Celeste.app");
+    write(
+        &celeste_dir.join("Contents/Resources/Content/Maps/0-Intro.bin"),
+        b"intro",
+    );
+
+    let mut rejected = vec![];
+    let installed_mods = scan_install(&celeste_dir, &ZipLimits::default(), &mut rejected).unwrap();
+
+    assert_eq!(installed_mods.len(), 1);
+    assert_eq!(installed_mods[0].kind, ModKind::Vanilla);
+    assert_eq!(
+        installed_mods[0].path,
+        celeste_dir.join("Contents/Resources/Content")
+    );
+    assert_eq!(map_names(&installed_mods[0]), ["Maps/0-Intro.bin"]);
+}
+
+#[test]
+fn unreadable_zips_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let celeste_dir = dir.path();
+    write(&celeste_dir.join("Mods/Broken.zip"), b"not a zip");
+    write(
+        &celeste_dir.join("Mods/Working.zip"),
+        &zip_with(&[("Maps/working.bin", "working")]),
+    );
+
+    let mut rejected = vec![];
+    let installed_mods = scan_install(celeste_dir, &ZipLimits::default(), &mut rejected).unwrap();
+
+    assert_eq!(installed_mods.len(), 1);
+    assert_eq!(installed_mods[0].name, "Working");
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].0, celeste_dir.join("Mods/Broken.zip"));
+}
+
+#[test]
+fn other_directories_are_not_an_install() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut rejected = vec![];
+    assert!(scan_install(dir.path(), &ZipLimits::default(), &mut rejected).is_err());
+}
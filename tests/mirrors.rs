@@ -0,0 +1,184 @@
+#![cfg(feature = "download")]
+
+mod common;
+
+use std::sync::Arc;
+
+use celeste_maps_data::{
+    config::{Config, MirrorRule},
+    download::{download_maps_with, source::HttpSource, DownloadOptions},
+};
+use common::{
+    file, map_mod, mod_zip, read_json, test_config, Failure, RecordedProgress, StandInServer,
+};
+
+// The original server lists the mod at /mmdl/1, the mirror has it at /files/1.zip.
+struct Servers {
+    original: StandInServer,
+    mirror: StandInServer,
+    bytes: Vec<u8>,
+}
+
+fn servers() -> Servers {
+    let original = StandInServer::start();
+    let mirror = StandInServer::start();
+    let bytes = mod_zip("a");
+    original.file(
+        "/mod_search_database.yaml",
+        serde_yaml::to_string(&[map_mod(
+            1,
+            "A",
+            vec![file(&original.url("/mmdl/1"), 1, &bytes)],
+        )])
+        .unwrap()
+        .into_bytes(),
+    );
+    Servers {
+        original,
+        mirror,
+        bytes,
+    }
+}
+
+fn mirror_config(dir: &std::path::Path, servers: &Servers, original_first: bool) -> Config {
+    let mut config = test_config(dir);
+    config.download.database_url = servers.original.url("/mod_search_database.yaml");
+    config.download.original_first = original_first;
+    config.download.mirrors = vec![MirrorRule {
+        name: "stand-in".to_string(),
+        prefix: servers.original.url("/mmdl/"),
+        replacement: servers.mirror.url("/files/"),
+        suffix: ".zip".to_string(),
+    }];
+    config
+}
+
+// URLs in the order they were tried.
+fn download(config: &Config) -> (usize, Vec<String>) {
+    let progress = Arc::new(RecordedProgress::default());
+    let failures = download_maps_with(
+        config,
+        &DownloadOptions::default(),
+        Arc::new(HttpSource::new(&config.download).unwrap()),
+        progress.clone(),
+    )
+    .unwrap();
+    let tried = progress
+        .events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|event| event["event"] == "started")
+        .map(|event| event["url"].as_str().unwrap().to_string())
+        .collect();
+    (failures, tried)
+}
+
+#[test]
+fn falls_back_to_the_mirror() {
+    let dir = tempfile::tempdir().unwrap();
+    let servers = servers();
+    servers.mirror.file("/files/1.zip", servers.bytes.clone());
+    let config = mirror_config(dir.path(), &servers, true);
+
+    let (failures, tried) = download(&config);
+
+    assert_eq!(failures, 0);
+    assert_eq!(
+        tried,
+        [
+            servers.original.url("/mmdl/1"),
+            servers.mirror.url("/files/1.zip")
+        ]
+    );
+    assert_eq!(
+        std::fs::read(config.paths.mod_zip(1)).unwrap(),
+        servers.bytes
+    );
+
+    let health = read_json(&config.paths.mirror_health());
+    assert_eq!(health["original"]["successes"], 0);
+    assert_eq!(health["original"]["failures"], 1);
+    assert_eq!(health["original"]["last_error"], "HTTP 404 Not Found");
+    assert_eq!(health["stand-in"]["successes"], 1);
+    assert_eq!(health["stand-in"]["failures"], 0);
+}
+
+#[test]
+fn tries_the_mirror_first() {
+    let dir = tempfile::tempdir().unwrap();
+    let servers = servers();
+    servers.mirror.file("/files/1.zip", servers.bytes.clone());
+    let config = mirror_config(dir.path(), &servers, false);
+
+    let (failures, tried) = download(&config);
+
+    assert_eq!(failures, 0);
+    assert_eq!(tried, [servers.mirror.url("/files/1.zip")]);
+    assert!(servers.original.requests("/mmdl/1").is_empty());
+
+    let health = read_json(&config.paths.mirror_health());
+    assert_eq!(health["stand-in"]["successes"], 1);
+    assert!(health.get("original").is_none());
+}
+
+#[test]
+fn failing_mirror_falls_back_to_the_original() {
+    let dir = tempfile::tempdir().unwrap();
+    let servers = servers();
+    servers.original.file("/mmdl/1", servers.bytes.clone());
+    servers
+        .mirror
+        .file("/files/1.zip", servers.bytes.clone())
+        .fail_next("/files/1.zip", Failure::Status(503));
+    let config = mirror_config(dir.path(), &servers, false);
+
+    let (failures, tried) = download(&config);
+
+    assert_eq!(failures, 0);
+    assert_eq!(
+        tried,
+        [
+            servers.mirror.url("/files/1.zip"),
+            servers.original.url("/mmdl/1")
+        ]
+    );
+
+    let health = read_json(&config.paths.mirror_health());
+    assert_eq!(health["stand-in"]["successes"], 0);
+    assert_eq!(health["stand-in"]["failures"], 1);
+    assert_eq!(
+        health["stand-in"]["last_error"],
+        "HTTP 503 Service Unavailable"
+    );
+    assert_eq!(health["original"]["successes"], 1);
+}
+
+#[test]
+fn retries_every_mirror_when_all_fail() {
+    let dir = tempfile::tempdir().unwrap();
+    let servers = servers();
+    servers
+        .original
+        .file("/mmdl/1", servers.bytes.clone())
+        .fail_next("/mmdl/1", Failure::Status(503));
+    servers
+        .mirror
+        .file("/files/1.zip", servers.bytes.clone())
+        .fail_next("/files/1.zip", Failure::Status(500));
+    let config = mirror_config(dir.path(), &servers, true);
+
+    let (failures, tried) = download(&config);
+
+    // Both fail on the first attempt, the second one starts from the original again.
+    let original = servers.original.url("/mmdl/1");
+    let mirror = servers.mirror.url("/files/1.zip");
+    assert_eq!(failures, 0);
+    assert_eq!(tried, [original.clone(), mirror, original]);
+
+    let health = read_json(&config.paths.mirror_health());
+    assert_eq!(health["original"]["failures"], 1);
+    assert_eq!(health["original"]["successes"], 1);
+    assert_eq!(health["stand-in"]["failures"], 1);
+    assert_eq!(health["stand-in"]["successes"], 0);
+}
@@ -0,0 +1,100 @@
+#![cfg(feature = "download")]
+
+mod common;
+
+use std::sync::Arc;
+
+use celeste_maps_data::download::{download_maps_with, source::MemorySource, DownloadOptions};
+use common::{file, map_mod, test_config, zip_with_bytes, RecordedProgress};
+
+const MAPS_URL: &str = "https://gamebanana.com/mmdl/1";
+const REJECTED_URL: &str = "https://gamebanana.com/mmdl/2";
+
+fn string(value: &str) -> Vec<u8> {
+    let mut bytes = vec![value.len() as u8];
+    bytes.extend_from_slice(value.as_bytes());
+    bytes
+}
+
+// A map with one room of the given size at 0, 0.
+fn map(width: i32, height: i32) -> Vec<u8> {
+    let mut bytes = vec![11];
+    bytes.extend_from_slice(b"CELESTE MAP");
+    bytes.extend(string("test"));
+    let lookup_table = ["Map", "levels", "level", "x", "y", "width", "height"];
+    bytes.extend((lookup_table.len() as i16).to_le_bytes());
+    for value in lookup_table {
+        bytes.extend(string(value));
+    }
+    // Map and levels, each with no attributes and one child.
+    for name in [0i16, 1] {
+        bytes.extend(name.to_le_bytes());
+        bytes.push(0);
+        bytes.extend(1i16.to_le_bytes());
+    }
+    // The room, with four int attributes and no children.
+    bytes.extend(2i16.to_le_bytes());
+    bytes.push(4);
+    for (attribute, value) in [(3i16, 0), (4, 0), (5, width), (6, height)] {
+        bytes.extend(attribute.to_le_bytes());
+        bytes.push(3);
+        bytes.extend(value.to_le_bytes());
+    }
+    bytes.extend(0i16.to_le_bytes());
+    bytes
+}
+
+#[test]
+fn process_writes_the_statistics_of_every_map() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let maps = zip_with_bytes(&[
+        ("Maps/a.bin", &map(320, 184)),
+        ("Maps/b.bin", &map(640, 368)),
+    ]);
+    let rejected = b"not a zip".to_vec();
+    let source = Arc::new(
+        MemorySource::new(vec![
+            map_mod(1, "Maps", vec![file(MAPS_URL, 1, &maps)]),
+            map_mod(2, "Rejected", vec![file(REJECTED_URL, 1, &rejected)]),
+        ])
+        .with_file(MAPS_URL, maps)
+        .with_file(REJECTED_URL, rejected),
+    );
+    let process = DownloadOptions {
+        process: true,
+        ..DownloadOptions::default()
+    };
+
+    let failures = download_maps_with(
+        &config,
+        &process,
+        source,
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+
+    assert_eq!(failures, 0);
+    let mut lines = std::fs::read_to_string(config.paths.map_statistics())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    lines.sort_by_key(|line| (line["gamebanana_id"].as_u64(), line["name"].to_string()));
+    assert_eq!(lines.len(), 3);
+
+    assert_eq!(lines[0]["gamebanana_id"], 1);
+    assert_eq!(lines[0]["mod_name"], "Maps");
+    assert_eq!(lines[0]["name"], "Maps/a.bin");
+    assert_eq!(lines[0]["statistics"]["rooms"], 1);
+    assert_eq!(lines[0]["statistics"]["width"], 320);
+    assert_eq!(lines[0]["error"], serde_json::Value::Null);
+    assert_eq!(lines[1]["name"], "Maps/b.bin");
+    assert_eq!(lines[1]["statistics"]["height"], 368);
+
+    // The rejected zip gets one line with the error and no map.
+    assert_eq!(lines[2]["gamebanana_id"], 2);
+    assert_eq!(lines[2]["name"], "");
+    assert_eq!(lines[2]["statistics"], serde_json::Value::Null);
+    assert!(lines[2]["error"].is_string());
+}
@@ -0,0 +1,121 @@
+#![cfg(feature = "download")]
+
+mod common;
+
+use std::sync::Arc;
+
+use celeste_maps_data::{
+    config::Config,
+    download::{download_maps_with, source::HttpSource, DownloadOptions},
+};
+use common::{file, map_mod, test_config, Failure, RecordedProgress, StandInServer};
+
+// Big enough that a partial download isn't the whole file, the content doesn't need to be a zip
+// until it's read.
+fn mod_bytes() -> Vec<u8> {
+    (0..64 * 1024).map(|i| (i % 251) as u8).collect()
+}
+
+fn serve_one_mod(dir: &std::path::Path, bytes: &[u8]) -> (StandInServer, Config) {
+    let server = StandInServer::start();
+    let mut config = test_config(dir);
+    config.download.database_url = server.url("/mod_search_database.yaml");
+    server.file("/mmdl/1", bytes.to_vec()).file(
+        "/mod_search_database.yaml",
+        serde_yaml::to_string(&[map_mod(
+            1,
+            "A",
+            vec![file(&server.url("/mmdl/1"), 1, bytes)],
+        )])
+        .unwrap()
+        .into_bytes(),
+    );
+    (server, config)
+}
+
+fn download(config: &Config) -> usize {
+    download_maps_with(
+        config,
+        &DownloadOptions::default(),
+        Arc::new(HttpSource::new(&config.download).unwrap()),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap()
+}
+
+fn part_path(config: &Config) -> std::path::PathBuf {
+    config.paths.mod_zip(1).with_extension("zip.part")
+}
+
+#[test]
+fn resumes_a_dropped_download() {
+    let dir = tempfile::tempdir().unwrap();
+    let bytes = mod_bytes();
+    let (server, config) = serve_one_mod(dir.path(), &bytes);
+    server.fail_next("/mmdl/1", Failure::DropAfter(10_000));
+
+    assert_eq!(download(&config), 0);
+
+    let requests = server.requests("/mmdl/1");
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].range, None);
+    assert_eq!(requests[1].range.as_deref(), Some("bytes=10000-"));
+    assert_eq!(std::fs::read(config.paths.mod_zip(1)).unwrap(), bytes);
+    assert!(!part_path(&config).exists());
+    assert!(!part_path(&config).with_extension("part.source").exists());
+}
+
+#[test]
+fn starts_over_when_the_server_sends_another_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let bytes = mod_bytes();
+    let (server, config) = serve_one_mod(dir.path(), &bytes);
+    server
+        .fail_next("/mmdl/1", Failure::DropAfter(10_000))
+        .fail_next("/mmdl/1", Failure::WrongRange);
+
+    assert_eq!(download(&config), 0);
+
+    let requests = server.requests("/mmdl/1");
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[1].range.as_deref(), Some("bytes=10000-"));
+    assert_eq!(requests[2].range, None);
+    assert_eq!(std::fs::read(config.paths.mod_zip(1)).unwrap(), bytes);
+    // Appending the whole file to the partial one would have failed the checksum.
+    assert!(!config.paths.quarantined_zip(1).exists());
+}
+
+#[test]
+fn discards_partial_downloads_of_other_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let bytes = mod_bytes();
+    let (server, config) = serve_one_mod(dir.path(), &bytes);
+    std::fs::create_dir_all(&config.paths.mods_dir).unwrap();
+    std::fs::write(part_path(&config), vec![0; 10_000]).unwrap();
+    std::fs::write(
+        part_path(&config).with_extension("part.source"),
+        r#"{"url":"https://gamebanana.com/mmdl/1","size":65536}"#,
+    )
+    .unwrap();
+
+    assert_eq!(download(&config), 0);
+
+    let requests = server.requests("/mmdl/1");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].range, None);
+    assert_eq!(std::fs::read(config.paths.mod_zip(1)).unwrap(), bytes);
+}
+
+#[test]
+fn discards_partial_downloads_without_a_source() {
+    let dir = tempfile::tempdir().unwrap();
+    let bytes = mod_bytes();
+    let (server, config) = serve_one_mod(dir.path(), &bytes);
+    std::fs::create_dir_all(&config.paths.mods_dir).unwrap();
+    std::fs::write(part_path(&config), vec![0; 10_000]).unwrap();
+
+    assert_eq!(download(&config), 0);
+
+    assert_eq!(server.requests("/mmdl/1")[0].range, None);
+    assert_eq!(std::fs::read(config.paths.mod_zip(1)).unwrap(), bytes);
+}
@@ -0,0 +1,115 @@
+#![cfg(feature = "download")]
+
+mod common;
+
+use std::{path::PathBuf, sync::Arc};
+
+use celeste_maps_data::download::{
+    download_maps_with,
+    source::{HttpSource, MemorySource},
+    DownloadOptions,
+};
+use common::{file, map_mod, mod_zip, read_json, test_config, RecordedProgress, StandInServer};
+
+fn run_reports(dir: &std::path::Path) -> Vec<PathBuf> {
+    let mut reports = std::fs::read_dir(dir.join("output").join("run_reports"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    reports.sort();
+    reports
+}
+
+#[test]
+fn each_run_gets_its_own_report() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let a = mod_zip("a");
+    let source = Arc::new(
+        MemorySource::new(vec![map_mod(1, "A", vec![file("https://a", 1, &a)])])
+            .with_file("https://a", a),
+    );
+
+    for _ in 0..2 {
+        download_maps_with(
+            &config,
+            &DownloadOptions::default(),
+            source.clone(),
+            Arc::new(RecordedProgress::default()),
+        )
+        .unwrap();
+    }
+
+    let reports = run_reports(dir.path());
+    assert_eq!(reports.len(), 2);
+    let first = read_json(&reports[0]);
+    let started_ms = reports[0]
+        .file_stem()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse::<u64>()
+        .unwrap();
+    assert_eq!(started_ms / 1000, first["started"]);
+    assert_eq!(first["totals"]["downloaded"], 1);
+    assert_eq!(first["error"], serde_json::Value::Null);
+    assert_eq!(read_json(&reports[1])["totals"]["skipped"], 1);
+}
+
+#[test]
+fn reports_of_runs_started_together_are_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let first = config.paths.run_report(1000);
+    std::fs::create_dir_all(first.parent().unwrap()).unwrap();
+    std::fs::write(&first, "{}").unwrap();
+
+    let second = config.paths.run_report(1000);
+
+    assert_ne!(first, second);
+    assert_eq!(second.file_name().unwrap(), "1000-1.json");
+}
+
+#[test]
+fn report_is_written_when_the_run_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = StandInServer::start();
+    let mut config = test_config(dir.path());
+    // Nothing is served, so fetching the mod database fails.
+    config.download.database_url = server.url("/mod_search_database.yaml");
+    config.paths.run_report = Some(dir.path().join("report.json"));
+
+    let result = download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        Arc::new(HttpSource::new(&config.download).unwrap()),
+        Arc::new(RecordedProgress::default()),
+    );
+
+    assert!(result.is_err());
+    let report = read_json(&dir.path().join("report.json"));
+    assert_eq!(report["error"], "HTTP 404 Not Found");
+    assert_eq!(report["totals"]["mods"], 0);
+}
+
+#[test]
+fn dry_runs_write_no_report() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = StandInServer::start();
+    let mut config = test_config(dir.path());
+    config.download.database_url = server.url("/mod_search_database.yaml");
+    let dry_run = DownloadOptions {
+        dry_run: true,
+        ..DownloadOptions::default()
+    };
+
+    let result = download_maps_with(
+        &config,
+        &dry_run,
+        Arc::new(HttpSource::new(&config.download).unwrap()),
+        Arc::new(RecordedProgress::default()),
+    );
+
+    assert!(result.is_err());
+    assert!(!dir.path().join("output").exists());
+}
@@ -0,0 +1,105 @@
+#![cfg(feature = "download")]
+
+mod common;
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use celeste_maps_data::download::{
+    download_maps_with,
+    source::{HttpSource, ModSource},
+    DownloadOptions,
+};
+use common::{file, map_mod, test_config, RecordedProgress, StandInServer};
+
+const CONCURRENCY: usize = 4;
+// Per connection, so a file's transfer time follows its size.
+const SERVER_RATE: u64 = 200 * 1024;
+
+// Two large files among small ones, the mix which made a batch wait for its slowest download.
+fn mod_sizes() -> Vec<usize> {
+    let mut sizes = vec![40 * 1024; 12];
+    sizes[0] = 400 * 1024;
+    sizes[5] = 400 * 1024;
+    sizes
+}
+
+fn mod_bytes(gamebanana_id: u64, size: usize) -> Vec<u8> {
+    (0..size)
+        .map(|i| (i as u64 * 31 + gamebanana_id) as u8)
+        .collect()
+}
+
+// The download loop before the scheduler, every batch of downloads had to finish before the next
+// batch started.
+async fn batch_loop(source: Arc<HttpSource>, urls: Vec<String>) {
+    for batch in urls.chunks(CONCURRENCY) {
+        let mut downloads = vec![];
+        for url in batch {
+            let source = source.clone();
+            let url = url.clone();
+            downloads.push(tokio::spawn(async move {
+                let mut fetched = source.fetch_file(&url, 0).await.unwrap();
+                while fetched.body.chunk().await.unwrap().is_some() {}
+            }));
+        }
+        for download in downloads {
+            download.await.unwrap();
+        }
+    }
+}
+
+// Run with `cargo test --test schedule -- --ignored --nocapture`, it takes several seconds.
+#[test]
+#[ignore]
+fn scheduler_is_faster_than_batches() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = StandInServer::start();
+    server.set_rate(SERVER_RATE);
+    let mut config = test_config(dir.path());
+    config.download.database_url = server.url("/mod_search_database.yaml");
+    config.download.concurrency = CONCURRENCY;
+
+    let mut mods = vec![];
+    let mut urls = vec![];
+    for (i, size) in mod_sizes().into_iter().enumerate() {
+        let gamebanana_id = i as u64 + 1;
+        let path = format!("/mmdl/{}", gamebanana_id);
+        let bytes = mod_bytes(gamebanana_id, size);
+        mods.push(map_mod(
+            gamebanana_id,
+            &format!("Mod {}", gamebanana_id),
+            vec![file(&server.url(&path), 1, &bytes)],
+        ));
+        urls.push(server.url(&path));
+        server.file(&path, bytes);
+    }
+    server.file(
+        "/mod_search_database.yaml",
+        serde_yaml::to_string(&mods).unwrap().into_bytes(),
+    );
+
+    let start = Instant::now();
+    let source = Arc::new(HttpSource::new(&config.download).unwrap());
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(batch_loop(source, urls));
+    let batches = start.elapsed();
+
+    let start = Instant::now();
+    let failures = download_maps_with(
+        &config,
+        &DownloadOptions::default(),
+        Arc::new(HttpSource::new(&config.download).unwrap()),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap();
+    let scheduler = start.elapsed();
+
+    assert_eq!(failures, 0);
+    println!("Batches: {:?}, scheduler: {:?}", batches, scheduler);
+    // About 4.2s against 2.6s, the margin leaves room for a slow machine.
+    assert!(scheduler + Duration::from_millis(500) < batches);
+}
@@ -0,0 +1,161 @@
+#![cfg(feature = "download")]
+
+mod common;
+
+use std::sync::Arc;
+
+use celeste_maps_data::{
+    config::Config,
+    download::{
+        checksum::audit_mods,
+        download_maps_with,
+        source::MemorySource,
+        store::{extract_mods, ModFiles},
+        DownloadOptions,
+    },
+};
+use common::{file, map_mod, test_config, zip_with, RecordedProgress};
+
+const MAP_URL: &str = "https://gamebanana.com/mmdl/1";
+const HELPER_URL: &str = "https://gamebanana.com/mmdl/2";
+
+const EVEREST_UPDATE: &str = "
+MapMod:
+  GameBananaType: Mod
+  GameBananaId: 1
+Helper:
+  GameBananaType: Mod
+  GameBananaId: 2
+";
+
+// A map which needs a helper, the helper isn't selected by the default categories.
+fn map_and_helper() -> MemorySource {
+    let map = zip_with(&[
+        ("Maps/test.bin", "map"),
+        (
+            "everest.yaml",
+            "- Name: MapMod\n  Version: 1.0.0\n  Dependencies:\n    - Name: Helper\n      Version: 1.0.0\n",
+        ),
+    ]);
+    let helper = zip_with(&[("everest.yaml", "- Name: Helper\n  Version: 1.0.0\n")]);
+    let mut helper_mod = map_mod(2, "Helper", vec![file(HELPER_URL, 1, &helper)]);
+    helper_mod.category_name = "Helpers".to_string();
+    MemorySource::new(vec![
+        map_mod(1, "Map", vec![file(MAP_URL, 1, &map)]),
+        helper_mod,
+    ])
+    .with_everest_update(EVEREST_UPDATE.to_string())
+    .with_file(MAP_URL, map)
+    .with_file(HELPER_URL, helper)
+}
+
+fn download(config: &Config, source: &Arc<MemorySource>, options: &DownloadOptions) -> usize {
+    download_maps_with(
+        config,
+        options,
+        source.clone(),
+        Arc::new(RecordedProgress::default()),
+    )
+    .unwrap()
+}
+
+fn with_dependencies() -> DownloadOptions {
+    DownloadOptions {
+        dependencies: true,
+        ..DownloadOptions::default()
+    }
+}
+
+#[test]
+fn reads_pruned_mods_from_the_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let source = Arc::new(map_and_helper());
+    download(&config, &source, &DownloadOptions::default());
+
+    let report = extract_mods(&config, true).unwrap();
+    assert_eq!(report.pruned, 1);
+    assert!(!config.paths.mod_zip(1).exists());
+
+    let mod_files = ModFiles::new(&config.paths, &config.zip).unwrap();
+    assert!(mod_files.is_downloaded(1));
+    assert!(!mod_files.is_downloaded(2));
+    let maps = mod_files.maps(1).unwrap().unwrap();
+    assert_eq!(maps.len(), 1);
+    assert_eq!(maps[0].name, "Maps/test.bin");
+    assert_eq!(maps[0].bytes, b"map");
+    assert!(mod_files.everest_yaml(1).unwrap().contains("Helper"));
+    assert!(mod_files.maps(2).unwrap().is_none());
+}
+
+#[test]
+fn follows_dependencies_of_pruned_mods() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let source = Arc::new(map_and_helper());
+    download(&config, &source, &DownloadOptions::default());
+    extract_mods(&config, true).unwrap();
+
+    assert_eq!(download(&config, &source, &with_dependencies()), 0);
+
+    assert_eq!(source.fetches(MAP_URL), 1);
+    assert_eq!(source.fetches(HELPER_URL), 1);
+    assert!(config.paths.mod_zip(2).is_file());
+    let graph = common::read_json(&config.paths.dependency_graph());
+    assert_eq!(graph["mods"]["1"]["dependencies"][0]["gamebanana_id"], 2);
+}
+
+#[test]
+fn pruned_dependencies_are_not_downloaded_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let source = Arc::new(map_and_helper());
+    download(&config, &source, &with_dependencies());
+    assert_eq!(source.fetches(HELPER_URL), 1);
+    extract_mods(&config, true).unwrap();
+
+    assert_eq!(download(&config, &source, &with_dependencies()), 0);
+
+    assert_eq!(source.fetches(HELPER_URL), 1);
+    assert!(!config.paths.mod_zip(2).exists());
+}
+
+#[test]
+fn refresh_compares_pruned_mods_by_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let source = Arc::new(map_and_helper());
+    download(&config, &source, &DownloadOptions::default());
+    extract_mods(&config, true).unwrap();
+    // As if the zip was downloaded before the manifest existed.
+    std::fs::remove_file(config.paths.manifest()).unwrap();
+
+    let refresh = DownloadOptions {
+        refresh: true,
+        ..DownloadOptions::default()
+    };
+    assert_eq!(download(&config, &source, &refresh), 0);
+
+    assert_eq!(source.fetches(MAP_URL), 1);
+    assert!(config.paths.manifest().is_file());
+}
+
+#[test]
+fn quarantined_zips_are_downloaded_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = test_config(dir.path());
+    let source = Arc::new(map_and_helper());
+    download(&config, &source, &DownloadOptions::default());
+    extract_mods(&config, false).unwrap();
+    std::fs::write(config.paths.mod_zip(1), b"corrupt").unwrap();
+
+    let report = audit_mods(&config).unwrap();
+    assert_eq!(report.quarantined.len(), 1);
+    assert!(!ModFiles::new(&config.paths, &config.zip)
+        .unwrap()
+        .is_downloaded(1));
+
+    assert_eq!(download(&config, &source, &DownloadOptions::default()), 0);
+    assert_eq!(source.fetches(MAP_URL), 2);
+    assert!(config.paths.mod_zip(1).is_file());
+}